@@ -1,23 +1,25 @@
-use clever_house::async_server::AsyncServer;
-use clever_house::smart_house::SmartHouse;
+use std::env;
+use std::path::Path;
+use smart_house::async_server::AsyncServer;
+use smart_house::config::ServerConfig;
+use smart_house::storage::DeviceState;
 
 fn main() {
-    // let own_addr = "127.0.0.1:8081";
-    // let remote_addr: & 'static str = "127.0.0.1:8082";
-    // let pool_size = 20;
-    //
-    // let mut smart_house = SmartHouse::new("smart_house", vec!["room1", "room2"]);
-    // smart_house.add_device("room1", "Smart_Socket_1").expect("could not add device");
-    //
-    // let server = Server {smart_house};
-    //
-    // server.start(own_addr, pool_size, remote_addr);
-    let addr = "127.0.0.1:8081";
+    let config = match env::args().nth(1) {
+        Some(path) => ServerConfig::read(Path::new(&path)).expect("could not read config"),
+        None => {
+            let mut config = ServerConfig::default();
+            config.house.rooms[0].devices.push(DeviceState {
+                name: String::from("Smart_Socket_1"),
+                kind: String::from("socket"),
+                is_on: false,
+                power: None,
+                brightness: None,
+                energy: None,
+            });
+            config
+        }
+    };
 
-    let mut smart_house = SmartHouse::new("smart_house", vec!["room1", "room2"]);
-    smart_house.add_device("room1", "Smart_Socket_1").expect("could not add device");
-    
-    let server = AsyncServer {smart_house};
-
-    server.start(addr);
-}
\ No newline at end of file
+    AsyncServer::start_from_config(config).expect("could not start server");
+}
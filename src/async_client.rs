@@ -1,14 +1,70 @@
+use std::collections::HashMap;
 use std::io::ErrorKind;
-use std::str::FromStr;
-use tokio::io;
+use std::net::SocketAddr;
+use std::ops::DerefMut;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use futures_core::Stream;
 use tokio::net::{TcpStream, ToSocketAddrs};
-use crate::{ARGUMENTS, END_MESSAGING_COMMAND, GET_SOCKET_CONSUMED_POWER, START_MESSAGING_COMMAND, SWITCH_SOCKET_COMMAND};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use crate::codec::{read_frame_async, write_frame_async};
+use crate::Command;
+use crate::automation::Rule;
+use crate::device_registry::DeviceConfig;
 use crate::errors::SmartHouseError;
-use crate::errors::DeviceError::SocketError;
-use crate::errors::SmartHouseError::{CommandError, NetworkError, ServerError, WrongRequestDataError};
+use crate::errors::SmartHouseError::{NetworkError, ProtocolError, ServerError};
+use crate::events::HouseEvent;
+use crate::group::DeviceGroup;
+use crate::history::{Bucket, HistoryQuery};
+use crate::report::HouseReport;
+use crate::poller::PollerStatus;
+use crate::scene::Scene;
+use crate::scheduler::{Job, Schedule};
+use crate::sensor::{SensorStatus, TemperatureStatus};
+use crate::protocol::{ClientMessage, ErrorCode, MemberResponse, Response, ServerMessage, SUPPORTED_VERSIONS};
 
+const CONNECTION_CLOSED : &str = "connection closed";
+
+// ожидающие ответа запросы по id; `None` - соединение закрыто и ответов больше не будет
+type PendingMap = Option<HashMap<u64, oneshot::Sender<Response>>>;
+type Pending = Arc<Mutex<PendingMap>>;
+
+/*
+    Запрос, ждущий ответа. Если ответа не дождались - отправка не удалась или вызывающий
+    бросил future, например по `timeout`, - запрос убирается из ожидающих при выходе.
+ */
+struct PendingRequest<'a> {
+    pending : &'a Pending,
+    id : u64,
+}
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        // на полученный ответ запись уже убрала задача, читающая ответы
+        if let Some(pending) = AsyncClient::lock(self.pending).as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/*
+    Клиент можно клонировать и использовать одновременно из нескольких задач: все клоны
+    работают через одно соединение. Ответы читает отдельная задача и по `id` отдаёт их
+    тем, кто их ждёт, поэтому сервер может отвечать в любом порядке.
+ */
+#[derive(Clone)]
 pub struct AsyncClient {
-    stream: TcpStream,
+    writer: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+    pending: Pending,
+    // адрес сервера, для подписки открывается отдельное соединение
+    addr: SocketAddr,
+    next_id: Arc<AtomicU64>,
+    // версия протокола, выбранная сервером при рукопожатии
+    version: u16,
 }
 
 impl AsyncClient {
@@ -17,99 +73,426 @@ impl AsyncClient {
         where
             Addrs: ToSocketAddrs,
     {
-        let stream = TcpStream::connect(addrs).await.expect("could not connect");
+        let stream = TcpStream::connect(addrs).await?;
+        let addr = stream.peer_addr()?;
+        let (mut reader, mut writer) = stream.into_split();
+        let version = Self::handshake(&mut reader, &mut writer).await?;
+        let pending : Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        tokio::spawn(Self::read_responses(reader, pending.clone()));
+        Ok(Self {
+            writer: Arc::new(tokio::sync::Mutex::new(writer)),
+            pending,
+            addr,
+            next_id: Arc::new(AtomicU64::new(1)),
+            version,
+        })
+    }
 
-        Ok(Self {stream})
+    pub fn get_version(&self) -> u16 {
+        self.version
     }
 
-    pub async fn switch_socket(&mut self, room_name: &str, device_name: &str, state : bool)
+    pub async fn switch_socket(&self, room_name: &str, device_name: &str, state : bool)
                          -> Result<bool, SmartHouseError>
     {
-        let command = String::from(START_MESSAGING_COMMAND) + "\n" + SWITCH_SOCKET_COMMAND
-            + "\n" + ARGUMENTS + "\n" + room_name + " " + device_name + " " + state.to_string().as_str()
-            + "\n" + " " + END_MESSAGING_COMMAND;
+        let command = Command::SwitchSocketCommand(
+            String::from(room_name), String::from(device_name), state);
+        self.request(command).await.map(|_| true)
+    }
 
-        let send = Self::send_request(self, command).await;
-        if send.is_err() {
-            println!("error while sending request {}", send.err().unwrap());
-            return Err(CommandError(SocketError("error while sending request to server")));
+    pub async fn get_consumed_power(&self, room_name: &str, device_name: &str)
+                              -> Result<f32, SmartHouseError>
+    {
+        let command = Command::GetSocketConsumedPower(String::from(room_name), String::from(device_name));
+        match self.request(command).await? {
+            Response::Power(power) => Ok(power),
+            _ => Err(ServerError("unexpected response"))
         }
-        match Self::receive_response(self).await {
-            Ok(_) => Ok(true),
-            Err(e) => Err(NetworkError(e))
+    }
+
+    pub async fn get_consumed_energy(&self, room_name: &str, device_name: &str)
+        -> Result<f64, SmartHouseError>
+    {
+        let command = Command::GetSocketConsumedEnergy(String::from(room_name), String::from(device_name));
+        match self.request(command).await? {
+            Response::Energy(energy) => Ok(energy),
+            _ => Err(ServerError("unexpected response"))
         }
     }
 
-    pub async fn get_consumed_power(&mut self, room_name: &str, device_name: &str)
-                              -> Result<f32, SmartHouseError>
+    pub async fn reset_energy(&self, room_name: &str, device_name: &str)
+        -> Result<bool, SmartHouseError>
     {
-        let command = String::from(START_MESSAGING_COMMAND) + "\n" + GET_SOCKET_CONSUMED_POWER
-            + "\n" + ARGUMENTS + "\n" + room_name + " " + device_name + "\n"
-            + END_MESSAGING_COMMAND;
+        let command = Command::ResetSocketEnergy(String::from(room_name), String::from(device_name));
+        self.request(command).await.map(|_| true)
+    }
+
+    pub async fn get_rooms(&self) -> Result<Vec<String>, SmartHouseError> {
+        match self.request(Command::GetRooms).await? {
+            Response::Rooms(rooms) => Ok(rooms),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn add_room(&self, room_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::AddRoom(String::from(room_name))).await.map(|_| true)
+    }
+
+    pub async fn remove_room(&self, room_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::RemoveRoom(String::from(room_name))).await.map(|_| true)
+    }
+
+    pub async fn get_devices(&self, room_name: &str) -> Result<Vec<String>, SmartHouseError> {
+        match self.request(Command::GetDevices(String::from(room_name))).await? {
+            Response::Devices(devices) => Ok(devices),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn add_device(&self, room_name: &str, device_kind: &str, config: DeviceConfig)
+        -> Result<bool, SmartHouseError>
+    {
+        let command = Command::AddDevice(String::from(room_name), String::from(device_kind), config);
+        self.request(command).await.map(|_| true)
+    }
+
+    pub async fn remove_device(&self, room_name: &str, device_name: &str)
+        -> Result<bool, SmartHouseError>
+    {
+        let command = Command::RemoveDevice(String::from(room_name), String::from(device_name));
+        self.request(command).await.map(|_| true)
+    }
+
+    // нет показаний или датчики отключились - ошибка `ErrorCode::NoData`
+    pub async fn get_thermo_data(&self) -> Result<TemperatureStatus, SmartHouseError> {
+        match self.request(Command::GetThermoData).await? {
+            Response::TemperatureStatus(status) => Ok(status),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_temperature(&self, room_name: &str, device_name: &str)
+        -> Result<TemperatureStatus, SmartHouseError>
+    {
+        let command = Command::GetTemperature(String::from(room_name), String::from(device_name));
+        match self.request(command).await? {
+            Response::TemperatureStatus(status) => Ok(status),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_room_temperature(&self, room_name: &str) -> Result<f32, SmartHouseError> {
+        match self.request(Command::GetRoomTemperature(String::from(room_name))).await? {
+            Response::Temperature(temperature) => Ok(temperature),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_sensors(&self) -> Result<Vec<SensorStatus>, SmartHouseError> {
+        match self.request(Command::GetSensors).await? {
+            Response::Sensors(sensors) => Ok(sensors),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_pollers(&self) -> Result<Vec<PollerStatus>, SmartHouseError> {
+        match self.request(Command::GetPollers).await? {
+            Response::Pollers(pollers) => Ok(pollers),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_history(&self, query: HistoryQuery) -> Result<Vec<Bucket>, SmartHouseError> {
+        match self.request(Command::GetHistory(query)).await? {
+            Response::History(buckets) => Ok(buckets),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_report(&self) -> Result<HouseReport, SmartHouseError> {
+        match self.request(Command::GetReport).await? {
+            Response::Report(report) => Ok(report),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_rules(&self) -> Result<Vec<Rule>, SmartHouseError> {
+        match self.request(Command::GetRules).await? {
+            Response::Rules(rules) => Ok(rules),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn set_rule_enabled(&self, rule_name: &str, enabled: bool) -> Result<bool, SmartHouseError> {
+        self.request(Command::SetRuleEnabled(String::from(rule_name), enabled)).await.map(|_| true)
+    }
+
+    pub async fn get_scenes(&self) -> Result<Vec<Scene>, SmartHouseError> {
+        match self.request(Command::GetScenes).await? {
+            Response::Scenes(scenes) => Ok(scenes),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn add_scene(&self, scene: Scene) -> Result<bool, SmartHouseError> {
+        self.request(Command::AddScene(scene)).await.map(|_| true)
+    }
 
-        let send = match Self::send_request(self, command).await {
-            Ok(()) => Ok(()),
-            Err(e) => Err(e)
+    pub async fn apply_scene(&self, scene_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::ApplyScene(String::from(scene_name))).await.map(|_| true)
+    }
+
+    pub async fn get_groups(&self) -> Result<Vec<DeviceGroup>, SmartHouseError> {
+        match self.request(Command::GetGroups).await? {
+            Response::Groups(groups) => Ok(groups),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn add_group(&self, group: DeviceGroup) -> Result<bool, SmartHouseError> {
+        self.request(Command::AddGroup(group)).await.map(|_| true)
+    }
+
+    pub async fn remove_group(&self, group_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::RemoveGroup(String::from(group_name))).await.map(|_| true)
+    }
+
+    pub async fn get_jobs(&self) -> Result<Vec<Job>, SmartHouseError> {
+        match self.request(Command::GetJobs).await? {
+            Response::Jobs(jobs) => Ok(jobs),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    // возвращает id нового задания
+    pub async fn add_job(&self, room_name: &str, device_name: &str, state: bool, schedule: Schedule)
+        -> Result<u64, SmartHouseError>
+    {
+        let command = Command::AddJob(String::from(room_name), String::from(device_name), state, schedule);
+        match self.request(command).await? {
+            Response::JobAdded(id) => Ok(id),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn remove_job(&self, id: u64) -> Result<bool, SmartHouseError> {
+        self.request(Command::RemoveJob(id)).await.map(|_| true)
+    }
+
+    // ошибки отдельных устройств возвращаются в их ответах, а не ошибкой всего запроса
+    pub async fn switch_group(&self, group_name: &str, state: bool) -> Result<Vec<MemberResponse>, SmartHouseError> {
+        match self.request(Command::SwitchGroup(String::from(group_name), state)).await? {
+            Response::Members(members) => Ok(members),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub async fn get_group_power(&self, group_name: &str) -> Result<Vec<MemberResponse>, SmartHouseError> {
+        match self.request(Command::GetGroupPower(String::from(group_name))).await? {
+            Response::Members(members) => Ok(members),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    /*
+        Подписка на события дома. Подписка занимает соединение целиком, поэтому
+        для неё открывается новое, а запросы этого клиента идут как раньше.
+     */
+    pub async fn subscribe(&self) -> Result<EventStream, SmartHouseError> {
+        let stream = TcpStream::connect(self.addr).await?;
+        let (mut reader, mut writer) = stream.into_split();
+        let version = Self::handshake(&mut reader, &mut writer).await?;
+        let request = ClientMessage::Request { version, id: 1, command: Command::Subscribe };
+        Self::send_message(&mut writer, &request).await?;
+        let buf = read_frame_async(&mut reader).await.map_err(NetworkError)?;
+        match ServerMessage::decode(&buf)? {
+            ServerMessage::Response { response, .. } => match response.into_result()? {
+                Response::Subscribed => {}
+                _ => return Err(ServerError("unexpected response"))
+            },
+            _ => return Err(ServerError("unexpected response"))
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::read_events(reader, writer, sender));
+        Ok(EventStream { receiver })
+    }
+
+    async fn handshake(reader: &mut OwnedReadHalf, writer: &mut OwnedWriteHalf) -> Result<u16, SmartHouseError> {
+        let hello = ClientMessage::Hello { versions: SUPPORTED_VERSIONS.to_vec() };
+        Self::send_message(writer, &hello).await?;
+        let buf = read_frame_async(reader).await.map_err(NetworkError)?;
+        match ServerMessage::decode(&buf)? {
+            ServerMessage::Hello { version: Some(version) } => Ok(version),
+            ServerMessage::Hello { version: None } =>
+                Err(ProtocolError(ErrorCode::UnsupportedVersion, String::from("no common protocol version"))),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    async fn request(&self, command: Command) -> Result<Response, SmartHouseError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        match Self::lock(&self.pending).as_mut() {
+            Some(pending) => pending.insert(id, sender),
+            None => return Err(ServerError(CONNECTION_CLOSED))
         };
+        let _request = PendingRequest { pending: &self.pending, id };
 
-        if send.is_ok() {
-            let recieved_message = Self::receive_response(self).await;
-            match recieved_message {
-                Ok(_) => {
-                    let consumed_power = f32::from_str(recieved_message.unwrap().as_str());
-                    match consumed_power {
-                        Ok(f) => Ok(f),
-                        Err(_) => Err(ServerError("could not parse data"))
-                    }
-                }
+        let message = ClientMessage::Request { version: self.version, id, command };
+        Self::send_message(self.writer.lock().await.deref_mut(), &message).await?;
+        // отправитель пропадает, только если соединение закрылось до ответа
+        receiver.await
+            .map_err(|_| ServerError(CONNECTION_CLOSED))?
+            .into_result()
+    }
+
+    // задача, читающая ответы сервера, пока соединение не закроется
+    async fn read_responses(mut reader: OwnedReadHalf, pending: Pending) {
+        loop {
+            let buf = match read_frame_async(&mut reader).await {
+                Ok(buf) => buf,
                 Err(e) => {
-                    println!("error : {e}");
-                    Err(NetworkError(e))
+                    println!("connection to server closed: {e}");
+                    break;
+                }
+            };
+            match ServerMessage::decode(&buf) {
+                Ok(ServerMessage::Response { id, response, .. }) => {
+                    let sender = Self::lock(&pending).as_mut().and_then(|p| p.remove(&id));
+                    match sender {
+                        // вызывающий мог перестать ждать ответ, это не ошибка
+                        Some(sender) => { let _ = sender.send(response); }
+                        None => println!("response to unknown request {id}: {response:?}")
+                    }
                 }
+                Ok(message) => println!("unexpected message from server: {message:?}"),
+                Err(e) => println!("could not decode message from server: {e}"),
             }
-        } else {
-            Err(NetworkError(send.err().unwrap()))
         }
+        // все, кто ещё ждёт ответа, получат ошибку
+        Self::lock(&pending).take();
     }
 
-    async fn receive_response(&mut self) -> Result<String, io::Error> {
-        let buf = &mut [0u8; 128];
-        let mut red = 0;
-        while red < buf.len() {
-            self.stream.readable().await?;
-            match self.stream.try_read(&mut buf[red..]) {
-                Ok(0) => break,
-                Ok(n) => {
-                    red += n;
+    // задача, читающая события, пока соединение открыто и `EventStream` не выброшен
+    async fn read_events(mut reader: OwnedReadHalf, _writer: OwnedWriteHalf,
+                         sender: UnboundedSender<Result<HouseEvent, SmartHouseError>>) {
+        loop {
+            let event = match read_frame_async(&mut reader).await {
+                Ok(buf) => match ServerMessage::decode(&buf) {
+                    Ok(ServerMessage::Event { event }) => Ok(event),
+                    Ok(_) => Err(ServerError("unexpected response")),
+                    Err(e) => Err(e),
+                },
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return,
+                Err(e) => {
+                    let _ = sender.send(Err(NetworkError(e)));
+                    return;
                 }
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
-                Err(e) => return Err(e),
+            };
+            if sender.send(event).is_err() {
+                return;
             }
-        };
-        let mut buf = buf.to_vec();
-        buf.truncate(red);
-        let resp = String::from_utf8(buf);
+        }
+    }
+
+    fn lock(pending: &Pending) -> MutexGuard<'_, PendingMap> {
+        pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        Ok(resp.unwrap())
+    async fn send_message(writer: &mut OwnedWriteHalf, message: &ClientMessage) -> Result<(), SmartHouseError> {
+        write_frame_async(writer, &message.encode()?).await.map_err(NetworkError)
     }
+}
 
-    async fn send_request(&mut self, command: String) -> Result<(), io::Error> {
-        let buf = &mut command.as_bytes();
-        let mut written = 0;
+// События дома по подписке. Поток заканчивается, когда сервер закрывает соединение.
+pub struct EventStream {
+    receiver: UnboundedReceiver<Result<HouseEvent, SmartHouseError>>,
+}
 
-        while written < buf.len() {
-            self.stream.writable().await?;
+impl EventStream {
+    pub async fn next_event(&mut self) -> Option<Result<HouseEvent, SmartHouseError>> {
+        self.receiver.recv().await
+    }
+}
 
-            match self.stream.try_write(&buf[written..]) {
-                Ok(0) => break,
-                Ok(n) => {
-                    written += n;
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                Err(e) => return Err(e),
+impl Stream for EventStream {
+    type Item = Result<HouseEvent, SmartHouseError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+    use crate::async_client::AsyncClient;
+    use crate::codec::{read_frame_async, write_frame_async};
+    use crate::Command;
+    use crate::protocol::{ClientMessage, PROTOCOL_VERSION, Response, ServerMessage};
+
+    #[tokio::test]
+    async fn test_out_of_order_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // сервер принимает два запроса и отвечает на них в обратном порядке
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_frame_async(&mut socket).await.unwrap();
+            let hello = ServerMessage::Hello { version: Some(PROTOCOL_VERSION) };
+            write_frame_async(&mut socket, &hello.encode().unwrap()).await.unwrap();
+
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let buf = read_frame_async(&mut socket).await.unwrap();
+                let Ok(ClientMessage::Request { id, command: Command::GetSocketConsumedPower(room, _), .. })
+                    = ClientMessage::decode(&buf) else { panic!("request expected") };
+                requests.push((id, room));
             }
-        }
+            for (id, room) in requests.into_iter().rev() {
+                let power = if room == "first" { 1.0 } else { 2.0 };
+                let reply = ServerMessage::Response { version: PROTOCOL_VERSION, id, response: Response::Power(power) };
+                write_frame_async(&mut socket, &reply.encode().unwrap()).await.unwrap();
+            }
+        });
+
+        let client = AsyncClient::connect(addr).await.unwrap();
+        let other = client.clone();
+        let (first, second) = tokio::join!(
+            client.get_consumed_power("first", "socket"),
+            other.get_consumed_power("second", "socket"));
+        assert_eq!(first.unwrap(), 1.0);
+        assert_eq!(second.unwrap(), 2.0);
+
+        // сервер закрыл соединение, следующий запрос завершается ошибкой, а не зависает
+        server.await.unwrap();
+        assert!(client.get_rooms().await.is_err());
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_cancelled_request_is_forgotten() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // сервер принимает запрос и не отвечает на него, пока клиент не закроет соединение
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_frame_async(&mut socket).await.unwrap();
+            let hello = ServerMessage::Hello { version: Some(PROTOCOL_VERSION) };
+            write_frame_async(&mut socket, &hello.encode().unwrap()).await.unwrap();
+            read_frame_async(&mut socket).await.unwrap();
+            assert!(read_frame_async(&mut socket).await.is_err());
+        });
+
+        let client = AsyncClient::connect(addr).await.unwrap();
+        assert!(timeout(Duration::from_millis(100), client.get_rooms()).await.is_err());
+        assert_eq!(AsyncClient::lock(&client.pending).as_ref().map(|p| p.len()), Some(0));
+
+        drop(client);
+        server.await.unwrap();
     }
-}
\ No newline at end of file
+}
@@ -1,172 +1,326 @@
-use std::ops::{Add, DerefMut};
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use tokio::io;
-use crate::errors::{DeviceError, SmartHouseError};
+use std::io::ErrorKind;
+use std::ops::DerefMut;
+use std::thread;
+use std::time::Duration;
+use std::sync::{Arc, Mutex, PoisonError};
+use crate::errors::SmartHouseError;
+use crate::history::DEFAULT_POWER_SAMPLE_SECS;
+use crate::poller::UdpPoller;
+use crate::scheduler::{SCHEDULER_TICK, Scheduler, SystemClock};
 use crate::smart_house::SmartHouse;
 use tokio::net::{TcpListener, TcpStream};
-use crate::{Command, END_MESSAGING_COMMAND, START_MESSAGING_COMMAND};
-use crate::errors::SmartHouseError::{ServerError, WrongRequestDataError};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Receiver;
+use tokio::task;
+use tokio::time::{interval, timeout};
+use std::path::{Path, PathBuf};
+use crate::codec::{read_frame_async, write_frame_async};
+use crate::config::{DEFAULT_IDLE_TIMEOUT_SECS, ServerConfig};
+use crate::events::{HouseEvent, SUBSCRIBER_QUEUE_SIZE};
+use crate::protocol::{handle_legacy_message, handle_message, is_legacy_frame, is_subscribed, ServerMessage};
 
 pub struct AsyncServer {
-    pub smart_house : SmartHouse
+    pub smart_house : SmartHouse,
+    // файл, в который сохраняется дом после каждого обработанного запроса
+    pub storage : Option<PathBuf>,
+    // соединение, по которому столько времени не было запросов, закрывается
+    pub idle_timeout : Duration,
+    // переключает розетки по расписанию, пока сервер работает
+    pub scheduler : Option<Scheduler>,
+    // как часто записывать мощность устройств в историю; `None` - не записывать
+    pub power_sampling : Option<Duration>,
 }
 
 impl AsyncServer {
 
+    pub fn new(smart_house: SmartHouse) -> Self {
+        let idle_timeout = Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS);
+        let power_sampling = Some(Duration::from_secs(DEFAULT_POWER_SAMPLE_SECS));
+        AsyncServer { smart_house, storage: None, idle_timeout, scheduler: None, power_sampling }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SmartHouseError> {
+        let mut server = Self::new(SmartHouse::load(path)?);
+        server.storage = Some(PathBuf::from(path));
+        Ok(server)
+    }
+
+    pub fn from_config(config: &ServerConfig) -> Result<Self, SmartHouseError> {
+        let smart_house = config.build_house()?;
+        let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+        let scheduler = config.build_scheduler(Box::new(SystemClock))?;
+        let power_sampling = config.power_sample_interval();
+        Ok(AsyncServer { smart_house, storage: config.storage.clone(), idle_timeout, scheduler, power_sampling })
+    }
+
+    // размер пула потоков из конфигурации асинхронному серверу не нужен
+    pub fn start_from_config(config: ServerConfig) -> Result<(), SmartHouseError> {
+        let server = Self::from_config(&config)?;
+        let remote_addrs = config.remote_addrs.iter()
+            .map(|a| a.as_str())
+            .collect::<Vec<&str>>();
+        server.start(&config.listen_addr, &remote_addrs);
+        Ok(())
+    }
+
     #[tokio::main]
-    pub async fn start(self, addr: &str) {
+    pub async fn start(self, addr: &str, remote_addrs: &[&str]) {
         let listener = TcpListener::bind(addr).await.expect("could not bind listener");
         println!("server started");
-        let arc =  Arc::new(Mutex::new(self.smart_house));
+        // планировщик живёт в доме, чтобы клиенты могли менять задания командами протокола
+        let mut smart_house = self.smart_house;
+        if let Some(scheduler) = self.scheduler {
+            smart_house.set_scheduler(scheduler);
+        }
+        let scheduled = smart_house.get_jobs().is_ok();
+        let arc =  Arc::new(Mutex::new(smart_house));
+        let storage = Arc::new(tokio::sync::Mutex::new(self.storage));
+        let idle_timeout = self.idle_timeout;
 
-        loop {
-            let (socket, _) = listener.accept().await.unwrap();
+        // приём показаний блокирующий, поэтому каждый адрес опрашивается в своём потоке, а не в задаче
+        let (changed, mut changes) = mpsc::unbounded_channel();
+        for remote_addr in remote_addrs {
+            let mut poller = UdpPoller::new(remote_addr);
+            let changed = changed.clone();
+            poller.on_rules_applied = Some(Box::new(move |_| { let _ = changed.send(()); }));
+            let arc_remote = arc.clone();
+            thread::spawn(move || poller.run(arc_remote));
+        }
+        // правила, сработавшие по показанию, сохраняются задачей: так запись в файл остаётся по очереди
+        {
             let arc = arc.clone();
-
+            let storage = storage.clone();
             tokio::spawn(async move {
-                let mut bytes = vec![0; 128];
-                let readable = socket.readable().await;
+                while changes.recv().await.is_some() {
+                    Self::save(&arc, &storage).await;
+                }
+            });
+        }
 
-                if readable.is_ok() {
-                    match socket.try_read(&mut bytes) {
-                        Ok(n) => {
-                            bytes.truncate(n);
-                        }
-                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                        Err(e) => {
-                            println!("error processing request: {e}");
+        // задания выполняются под той же блокировкой, что и запросы клиентов
+        if scheduled {
+            let arc = arc.clone();
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let mut ticks = interval(SCHEDULER_TICK);
+                loop {
+                    ticks.tick().await;
+                    let ran = {
+                        let mut smart_house = arc.lock().unwrap_or_else(PoisonError::into_inner);
+                        let ran = !smart_house.run_scheduled_jobs().is_empty();
+                        if ran {
+                            smart_house.apply_rules();
                         }
+                        ran
+                    };
+                    if ran {
+                        Self::save(&arc, &storage).await;
                     }
-                } else {
-                    let err = readable.err().unwrap();
-                    println!("stream.readable() error {err}")
                 }
-                let command = String::from_utf8(bytes.clone()).unwrap();
-                println!("command from client: {:?}", &command);
-
-                match Self::process_request(arc, bytes).await {
-                    Ok(resp) => {
-                        println!("request proceed successfully");
-                        let send = Self::send_response(socket, &resp).await;
-                        if send.is_ok() {
-                            println!("response sent to client");
-                        } else {
-                            println!("error while sending response : {}", send.err().unwrap());
-                        }
-                    }
-                    Err(e) => { println!("request failed with error: {e}")}
+            });
+        }
+
+        if let Some(period) = self.power_sampling {
+            let arc = arc.clone();
+            tokio::spawn(async move {
+                let mut ticks = interval(period);
+                loop {
+                    ticks.tick().await;
+                    arc.lock().unwrap_or_else(PoisonError::into_inner).record_power();
+                }
+            });
+        }
+
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    println!("could not accept connection: {e}");
+                    continue;
                 }
+            };
+            let arc = arc.clone();
+            let storage = storage.clone();
+
+            tokio::spawn(async move {
+                Self::handle_connection(arc, socket, storage, idle_timeout).await
             });
         }
     }
 
-    async fn process_request(smart_house: Arc<Mutex<SmartHouse>>, bytes: Vec<u8>)
-        -> Result<String, SmartHouseError> {
-
-        let lock = smart_house.lock();
-        if lock.is_ok() {
-            let mut lock = lock.unwrap();
-            match Self::parse_command(bytes) {
-                Ok(Command::SwitchSocketCommand(room, device, state)) => {
-                    let res = Self::switch_socket(lock.deref_mut(), &room, &device, state);
-                    if res.is_ok() {
-                        let resp = String::from("socket ")
-                            .add(device.as_str())
-                            .add(" switched successfully");
-                        Ok(resp)
-                    } else {
-                        Err(SmartHouseError::CommandError(DeviceError::SocketError("could not switch socket")))
-                    }
+    /*
+        Соединение обслуживает запросы один за другим, пока клиент его не закроет
+        или пока за `idle_timeout` не придёт ни одного кадра.
+     */
+    async fn handle_connection(smart_house: Arc<Mutex<SmartHouse>>, mut socket: TcpStream,
+                               storage: Arc<tokio::sync::Mutex<Option<PathBuf>>>, idle_timeout: Duration) {
+        loop {
+            let bytes = match timeout(idle_timeout, read_frame_async(&mut socket)).await {
+                Ok(Ok(bytes)) => bytes,
+                Ok(Err(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    println!("client closed connection");
+                    return;
                 }
-                Ok(Command::GetSocketConsumedPower(room, device)) => {
-                    let res = Self::get_socket_state(lock.deref_mut(), &room, &device);
-                    if res.is_ok() {
-                        let power = res.unwrap();
-                        println!("power of socket : {device} is {power}");
-                        Ok(power.to_string())
-                    } else {
-                        let err = res.err().unwrap();
-                        println!("error while getting consumed power {err}");
-                        Err(SmartHouseError::CommandError(DeviceError::SocketError("could not get socket consumed")))
-                    }
+                Ok(Err(e)) => {
+                    println!("error processing request: {e}");
+                    return;
                 }
                 Err(_) => {
-                    Err(ServerError("Could not parse command") )
+                    println!("connection closed after idle timeout");
+                    return;
                 }
+            };
+
+            let (reply, events, changed) = Self::process_frame(&smart_house, &bytes);
+            // запросы на чтение дом не меняют, переписывать файл после них незачем
+            if changed {
+                Self::save(&smart_house, &storage).await;
+            }
+            let sent = match reply {
+                Ok(reply) => write_frame_async(&mut socket, &reply).await.map_err(SmartHouseError::from),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = sent {
+                println!("error while sending response : {e}");
+                return;
+            }
+            if let Some(events) = events {
+                Self::send_events(socket, events).await;
+                return;
             }
-        } else {
-            Err(ServerError("Internal Server Error"))
         }
     }
 
-    fn parse_command(bytes: Vec<u8>) -> Result<Command, SmartHouseError> {
-        let commands = String::from_utf8(bytes).unwrap()
-            .split('\n')
-            .map(String::from)
-            .collect::<Vec<String>>();
-
-        if !commands.get(0).unwrap().contains(START_MESSAGING_COMMAND) {
-            return Err(WrongRequestDataError("wrong start command"));
+    /*
+        Доставка событий подписчику, пока он не закроет соединение. Когда задача завершается,
+        `receiver` пропадает и слушатель в доме отписывается при следующем событии.
+        Если очередь подписчика переполнилась, слушатель отписывается сам, и после
+        уже стоящих в очереди событий соединение закрывается.
+     */
+    async fn send_events(mut socket: TcpStream, mut receiver: Receiver<HouseEvent>) {
+        println!("client subscribed to events");
+        let (mut reader, mut writer) = socket.split();
+        // запросы от подписчика не ожидаются, читаем только чтобы заметить закрытие соединения
+        let mut buf = [0; 256];
+        loop {
+            tokio::select! {
+                event = receiver.recv() => {
+                    let Some(event) = event else { return };
+                    let sent = match (ServerMessage::Event { event }).encode() {
+                        Ok(bytes) => write_frame_async(&mut writer, &bytes).await.map_err(SmartHouseError::from),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = sent {
+                        println!("subscriber disconnected : {e}");
+                        return;
+                    }
+                }
+                read = reader.read(&mut buf) => {
+                    if !matches!(read, Ok(n) if n > 0) {
+                        println!("subscriber disconnected");
+                        return;
+                    }
+                }
+            }
         }
+    }
 
-        let command = commands.get(1).unwrap();
-        println!("command: {:#?}", &command);
-
-        let args = commands.iter()
-            .skip(3)
-            .take_while(|c| !c.eq(&&END_MESSAGING_COMMAND))
-            .flat_map(|s| s.split(' '))
-            .map(String::from)
-            .filter(|e| e.ne(&String::from("")))
-            .collect::<Vec<String>>();
-
-        match command.as_str() {
-            crate::SWITCH_SOCKET_COMMAND => {
-                Ok(Command::SwitchSocketCommand(
-                    String::from(args.get(0).unwrap().as_str()),
-                    String::from(args.get(1).unwrap().as_str()),
-                    bool::from_str(args.get(2).unwrap().to_string().as_str()).unwrap()))
-            }
-            crate::GET_SOCKET_CONSUMED_POWER => {
-                Ok(Command::GetSocketConsumedPower(
-                    String::from(args.get(0).unwrap().as_str()),
-                    String::from(args.get(1).unwrap().as_str())
-                ))
+    // ответ на кадр, подписка, если клиент подписался, и изменился ли дом
+    fn process_frame(smart_house: &Arc<Mutex<SmartHouse>>, bytes: &[u8])
+        -> (Result<Vec<u8>, SmartHouseError>, Option<Receiver<HouseEvent>>, bool) {
+
+        // мьютекс мог быть отравлен паникой в другой задаче, данные дома при этом остаются целыми
+        let mut lock = smart_house.lock().unwrap_or_else(PoisonError::into_inner);
+        let revision = lock.get_revision();
+        let mut events = None;
+        let reply = if is_legacy_frame(bytes) {
+            println!("command from client: {:?}", String::from_utf8_lossy(bytes));
+            Ok(handle_legacy_message(lock.deref_mut(), bytes).into_bytes())
+        } else {
+            let message = handle_message(lock.deref_mut(), bytes);
+            // подписываемся под той же блокировкой, чтобы не пропустить ни одного события
+            if is_subscribed(&message) {
+                let (sender, receiver) = mpsc::channel(SUBSCRIBER_QUEUE_SIZE);
+                lock.subscribe(move |event| sender.try_send(event.clone()).is_ok());
+                events = Some(receiver);
             }
-            _ => todo!()
+            message.encode()
+        };
+        (reply, events, lock.get_revision() != revision)
+    }
+
+    /*
+        Снимок дома берётся под блокировкой, а в файл пишется в потоке для блокирующих операций,
+        чтобы не занимать потоки рантайма. Пока пишет одна задача, другие ждут `storage`:
+        иначе более старый снимок мог бы оказаться в файле последним.
+     */
+    async fn save(smart_house: &Mutex<SmartHouse>, storage: &tokio::sync::Mutex<Option<PathBuf>>) {
+        let storage = storage.lock().await;
+        let Some(path) = storage.as_ref() else { return };
+        let state = smart_house.lock().unwrap_or_else(PoisonError::into_inner).to_state();
+        let target = path.clone();
+        match task::spawn_blocking(move || state.write(&target)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("could not save smart house to {}: {e}", path.display()),
+            Err(e) => println!("could not save smart house to {}: {e}", path.display()),
         }
     }
+}
 
-    async fn send_response(stream: TcpStream, resp: &str) ->  Result<usize, SmartHouseError> {
-        let buf = &mut resp.as_bytes();
-        let mut written = 0;
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+    use tokio::sync::mpsc::error::TryRecvError;
+    use crate::Command;
+    use crate::async_client::AsyncClient;
+    use crate::async_server::AsyncServer;
+    use crate::events::SUBSCRIBER_QUEUE_SIZE;
+    use crate::protocol::{ClientMessage, PROTOCOL_VERSION};
+    use crate::smart_house::SmartHouse;
 
-        while written < buf.len() {
-            stream.writable().await?;
+    #[test]
+    fn test_lagging_subscriber_is_dropped() {
+        let smart_house = Arc::new(Mutex::new(SmartHouse::new("SmartHouse", vec!["hall"])));
+        let subscribe = ClientMessage::Request { version: PROTOCOL_VERSION, id: 1, command: Command::Subscribe }
+            .encode().unwrap();
+        let (reply, events, _) = AsyncServer::process_frame(&smart_house, &subscribe);
+        assert!(reply.is_ok());
+        let mut events = events.expect("no subscription");
 
-            match stream.try_write(&buf[written..]) {
-                Ok(0) => break,
-                Ok(n) => {
-                    written += n;
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                Err(e) => return Err(SmartHouseError::NetworkError(e)),
-            }
+        // подписчик ничего не читает, а события продолжают приходить
+        for i in 0..=SUBSCRIBER_QUEUE_SIZE {
+            smart_house.lock().unwrap().set_thermo_data(i as f32 + 1.0);
         }
-
-        Ok(written)
+        for _ in 0..SUBSCRIBER_QUEUE_SIZE {
+            assert!(events.try_recv().is_ok());
+        }
+        // переполнившая очередь подписка снята, новых событий не будет
+        assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Disconnected);
     }
 
-    fn switch_socket(smart_house: &mut SmartHouse, room: &str, device : &str, is_on : bool)
-                     -> Result<bool, SmartHouseError>
-    {
-        smart_house.switch_socket( room,  device, is_on)
-    }
+    #[tokio::test]
+    async fn test_connection_serves_requests_until_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let smart_house = Arc::new(Mutex::new(SmartHouse::new("SmartHouse", vec!["hall"])));
+        let connection = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let storage = Arc::new(tokio::sync::Mutex::new(None));
+            AsyncServer::handle_connection(smart_house, socket, storage, Duration::from_millis(200)).await;
+        });
+
+        // рукопожатие и несколько запросов по одному соединению
+        let client = AsyncClient::connect(addr).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(client.get_rooms().await.unwrap(), vec!["hall"]);
+        }
 
-    fn get_socket_state (smart_house: &mut SmartHouse, room: &str, device : &str)
-                         -> Result<f32, SmartHouseError>
-    {
-        smart_house.get_socket_state(room, device)
+        // без запросов дольше `idle_timeout` сервер закрывает соединение
+        timeout(Duration::from_secs(5), connection).await.unwrap().unwrap();
+        assert!(client.get_rooms().await.is_err());
     }
-}
\ No newline at end of file
+}
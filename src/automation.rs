@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::errors::SmartHouseError;
+use crate::errors::SmartHouseError::{SerializationError, StorageError};
+use crate::smart_house::SmartHouse;
+
+// Условие срабатывания правила.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    // значение удалённого термометра, °C; без показаний или от отключившихся датчиков не выполняется
+    TemperatureAbove(f32),
+    TemperatureBelow(f32),
+    // суммарная мощность устройств комнаты, Вт
+    RoomPowerAbove { room : String, power : f32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    SwitchSocket { room : String, device : String, state : bool },
+    // выключает первую включённую розетку из списка, упорядоченного от низшего приоритета к высшему
+    SwitchOffLowestPriority { room : String, priorities : Vec<String> },
+}
+
+/*
+    Правило автоматизации: пока условие выполняется, действие приводит дом в нужное
+    состояние. Действие, которому нечего менять, ничего не делает и срабатыванием не считается.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub name : String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled : bool,
+    pub condition : Condition,
+    pub action : Action,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl Condition {
+    pub fn is_met(&self, smart_house: &mut SmartHouse) -> Result<bool, SmartHouseError> {
+        match self {
+            Condition::TemperatureAbove(limit) => Ok(smart_house.get_remote_temperature().is_ok_and(|t| t > *limit)),
+            Condition::TemperatureBelow(limit) => Ok(smart_house.get_remote_temperature().is_ok_and(|t| t < *limit)),
+            Condition::RoomPowerAbove { room, power } => Ok(smart_house.get_room_power(room)? > *power),
+        }
+    }
+}
+
+impl Action {
+    // возвращает `true`, если состояние дома изменилось
+    pub fn apply(&self, smart_house: &mut SmartHouse) -> Result<bool, SmartHouseError> {
+        match self {
+            Action::SwitchSocket { room, device, state } => {
+                if smart_house.is_switched_on(room, device)? == *state {
+                    return Ok(false);
+                }
+                smart_house.switch_socket(room, device, *state)
+            }
+            Action::SwitchOffLowestPriority { room, priorities } => {
+                // устройство из списка могли удалить, тогда правило переходит к следующему
+                for device in priorities {
+                    if matches!(smart_house.is_switched_on(room, device), Ok(true)) {
+                        return smart_house.switch_socket(room, device, false);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Rule {
+    // возвращает `true`, если правило сработало и изменило дом
+    pub fn evaluate(&self, smart_house: &mut SmartHouse) -> Result<bool, SmartHouseError> {
+        if !self.enabled || !self.condition.is_met(smart_house)? {
+            return Ok(false);
+        }
+        self.action.apply(smart_house)
+    }
+}
+
+// Правила хранятся в JSON файле списком.
+pub fn read_rules(path: &Path) -> Result<Vec<Rule>, SmartHouseError> {
+    let data = fs::read_to_string(path).map_err(StorageError)?;
+    serde_json::from_str(&data).map_err(SerializationError)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::automation::{Action, Condition, Rule};
+    use crate::Command;
+    use crate::device_registry::{DeviceConfig, SOCKET_KIND};
+    use crate::protocol::execute;
+    use crate::sensor::SensorTimeouts;
+    use crate::smart_house::SmartHouse;
+
+    #[test]
+    fn test_rules() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1", "room2"]);
+        let mut heater = DeviceConfig::new("Socket_Heater");
+        heater.is_on = true;
+        heater.power = Some(1000.0);
+        smart_house.add_device("room1", SOCKET_KIND, heater).unwrap();
+        for (name, power) in [("Kettle", 30.0), ("Tv", 20.0), ("Fridge", 15.0)] {
+            let mut config = DeviceConfig::new(name);
+            config.is_on = true;
+            config.power = Some(power);
+            smart_house.add_device("room2", SOCKET_KIND, config).unwrap();
+        }
+
+        let rules : Vec<Rule> = serde_json::from_str(r#"[
+            { "name": "heater off when hot",
+              "condition": { "TemperatureAbove": 27.0 },
+              "action": { "SwitchSocket": { "room": "room1", "device": "Socket_Heater", "state": false } } },
+            { "name": "room2 power limit",
+              "condition": { "RoomPowerAbove": { "room": "room2", "power": 50.0 } },
+              "action": { "SwitchOffLowestPriority": { "room": "room2", "priorities": ["Tv", "Kettle"] } } }
+        ]"#).expect("error parsing rules");
+        assert!(rules.iter().all(|r| r.enabled));
+        smart_house.set_rules(rules);
+
+        // 65 Вт в room2: выключается только Tv, после этого мощность в пределах лимита
+        assert_eq!(smart_house.apply_rules(), vec![String::from("room2 power limit")]);
+        assert!(!smart_house.is_switched_on("room2", "Tv").unwrap());
+        assert!(smart_house.is_switched_on("room2", "Kettle").unwrap());
+
+        smart_house.set_thermo_data(26.0);
+        assert!(smart_house.apply_rules().is_empty());
+        assert!(smart_house.is_switched_on("room1", "Socket_Heater").unwrap());
+
+        smart_house.set_rule_enabled("heater off when hot", false).unwrap();
+        smart_house.set_thermo_data(28.0);
+        assert!(smart_house.apply_rules().is_empty());
+        smart_house.set_rule_enabled("heater off when hot", true).unwrap();
+        assert_eq!(smart_house.apply_rules(), vec![String::from("heater off when hot")]);
+        assert!(!smart_house.is_switched_on("room1", "Socket_Heater").unwrap());
+        // повторно выключать нечего
+        assert!(smart_house.apply_rules().is_empty());
+
+        // все розетки из списка приоритетов выключены, а лимит всё ещё превышен
+        smart_house.switch_socket("room2", "Tv", true).unwrap();
+        let mut boiler = DeviceConfig::new("Boiler");
+        boiler.is_on = true;
+        boiler.power = Some(100.0);
+        smart_house.add_device("room2", SOCKET_KIND, boiler).unwrap();
+        assert_eq!(smart_house.apply_rules().len(), 2);
+        assert!(!smart_house.is_switched_on("room2", "Kettle").unwrap());
+        assert!(smart_house.apply_rules().is_empty());
+
+        assert!(smart_house.set_rule_enabled("no such rule", false).is_err());
+
+        // Tv удалили, а правило всё ещё ссылается на него
+        smart_house.remove_device("room2", "Tv").unwrap();
+        smart_house.switch_socket("room2", "Kettle", true).unwrap();
+        // команда на чтение правила не запускает, команда, изменившая дом, - запускает
+        execute(&mut smart_house, Command::GetRooms);
+        assert!(smart_house.is_switched_on("room2", "Kettle").unwrap());
+        execute(&mut smart_house, Command::AddRoom(String::from("hall")));
+        assert!(!smart_house.is_switched_on("room2", "Kettle").unwrap());
+    }
+
+    #[test]
+    fn test_temperature_rules_need_fresh_data() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket_Heater")).unwrap();
+        smart_house.set_rules(vec![Rule {
+            name: String::from("heater on when cold"),
+            enabled: true,
+            condition: Condition::TemperatureBelow(18.0),
+            action: Action::SwitchSocket { room: String::from("room1"), device: String::from("Socket_Heater"), state: true },
+        }]);
+
+        // показаний ещё не было: 0 °C по умолчанию - это не холод
+        assert!(smart_house.apply_rules().is_empty());
+        assert!(!smart_house.is_switched_on("room1", "Socket_Heater").unwrap());
+
+        // датчики замолчали: по старому показанию розетку уже не включают
+        smart_house.set_thermo_data(15.0);
+        smart_house.set_sensor_timeouts(SensorTimeouts { stale_after: Duration::ZERO, offline_after: Duration::ZERO });
+        assert!(smart_house.apply_rules().is_empty());
+        assert!(!smart_house.is_switched_on("room1", "Socket_Heater").unwrap());
+
+        smart_house.set_sensor_timeouts(SensorTimeouts::default());
+        assert_eq!(smart_house.apply_rules(), vec![String::from("heater on when cold")]);
+        assert!(smart_house.is_switched_on("room1", "Socket_Heater").unwrap());
+    }
+}
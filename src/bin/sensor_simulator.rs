@@ -0,0 +1,15 @@
+use std::env;
+use std::path::Path;
+use smart_house::remote_server::{RemoteServer, SimulatorConfig};
+
+// Симулятор удалённых датчиков для локальной проверки сервера без настоящих устройств.
+fn main() {
+    // путь к файлу конфигурации можно передать первым аргументом
+    let config = match env::args().nth(1) {
+        Some(path) => SimulatorConfig::read(Path::new(&path)).expect("could not read simulator config"),
+        None => SimulatorConfig::default()
+    };
+
+    println!("simulating {} sensor(s) for {:?}", config.sensors, config.targets);
+    RemoteServer::run(config).expect("sensor simulator stopped");
+}
@@ -1,93 +1,312 @@
-use std::io;
-use std::io::{ErrorKind, Read, Write};
+use std::io::ErrorKind;
 use std::net::{TcpStream, ToSocketAddrs};
-use std::str::FromStr;
+use crate::codec::{read_frame, write_frame};
+use crate::Command;
+use crate::automation::Rule;
+use crate::device_registry::DeviceConfig;
 use crate::errors::{SmartHouseError};
-use crate::{SWITCH_SOCKET_COMMAND, START_MESSAGING_COMMAND, ARGUMENTS, END_MESSAGING_COMMAND, GET_SOCKET_CONSUMED_POWER};
-use crate::errors::SmartHouseError::{NetworkError, ServerError};
+use crate::errors::SmartHouseError::{NetworkError, ProtocolError, ServerError};
+use crate::events::HouseEvent;
+use crate::group::DeviceGroup;
+use crate::history::{Bucket, HistoryQuery};
+use crate::report::HouseReport;
+use crate::poller::PollerStatus;
+use crate::scene::Scene;
+use crate::scheduler::{Job, Schedule};
+use crate::sensor::{SensorStatus, TemperatureStatus};
+use crate::protocol::{ClientMessage, ErrorCode, MemberResponse, Response, ServerMessage, SUPPORTED_VERSIONS};
 
 pub struct Client {
     stream: TcpStream,
+    // версия протокола, выбранная сервером при рукопожатии
+    version: u16,
+    // id следующего запроса
+    next_id: u64,
 }
 
 impl Client {
 
-    pub fn connect<Addrs>( addrs: Addrs) -> Result<Self, io::Error>
+    pub fn connect<Addrs>( addrs: Addrs) -> Result<Self, SmartHouseError>
         where
             Addrs: ToSocketAddrs,
     {
         let stream = TcpStream::connect(addrs)?;
+        let mut client = Self { stream, version: 0, next_id: 1 };
+        client.version = client.handshake()?;
+        Ok(client)
+    }
 
-        Ok(Self {stream})
+    pub fn get_version(&self) -> u16 {
+        self.version
     }
 
     pub fn switch_socket(&mut self, room_name: &str, device_name: &str, state : bool)
         -> Result<bool, SmartHouseError>
     {
-        let command = String::from(START_MESSAGING_COMMAND) + "\n" + SWITCH_SOCKET_COMMAND
-            + "\n" + ARGUMENTS + "\n" + room_name + " " + device_name + " " + state.to_string().as_str()
-            + "\n" + " " + END_MESSAGING_COMMAND;
+        let command = Command::SwitchSocketCommand(
+            String::from(room_name), String::from(device_name), state);
+        self.request(command).map(|_| true)
+    }
 
-        let send = Self::send_request(self, command);
-        if send.is_err() {
-            Self::send_request(self, String::from("unknown error")).expect("failed to send bites");
+    pub fn get_consumed_power(&mut self, room_name: &str, device_name: &str)
+                         -> Result<f32, SmartHouseError>
+    {
+        let command = Command::GetSocketConsumedPower(String::from(room_name), String::from(device_name));
+        match self.request(command)? {
+            Response::Power(power) => Ok(power),
+            _ => Err(ServerError("unexpected response"))
         }
-        match Self::receive_response(self) {
-            Ok(_) => Ok(true),
-            Err(e) => Err(NetworkError(e))
+    }
+
+    pub fn get_consumed_energy(&mut self, room_name: &str, device_name: &str)
+        -> Result<f64, SmartHouseError>
+    {
+        let command = Command::GetSocketConsumedEnergy(String::from(room_name), String::from(device_name));
+        match self.request(command)? {
+            Response::Energy(energy) => Ok(energy),
+            _ => Err(ServerError("unexpected response"))
         }
     }
 
-    pub fn get_consumed_power(&mut self, room_name: &str, device_name: &str)
-                         -> Result<f32, SmartHouseError>
+    pub fn reset_energy(&mut self, room_name: &str, device_name: &str)
+        -> Result<bool, SmartHouseError>
     {
-        let command = String::from(START_MESSAGING_COMMAND) + "\n" + GET_SOCKET_CONSUMED_POWER
-            + "\n" + ARGUMENTS + "\n" + room_name + " " + device_name + "\n"
-            + END_MESSAGING_COMMAND;
+        let command = Command::ResetSocketEnergy(String::from(room_name), String::from(device_name));
+        self.request(command).map(|_| true)
+    }
 
-        let send = match Self::send_request(self, command) {
-            Ok(()) => Ok(true),
-            Err(e) => Err(e)
-        };
+    pub fn get_rooms(&mut self) -> Result<Vec<String>, SmartHouseError> {
+        match self.request(Command::GetRooms)? {
+            Response::Rooms(rooms) => Ok(rooms),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
 
-        if send.is_ok() {
-            let recieved_message = Self::receive_response(self);
-            match recieved_message {
-                Ok(_) => {
-                    let consumed_power = f32::from_str(recieved_message.unwrap().as_str());
-                    match consumed_power {
-                        Ok(f) => Ok(f),
-                        Err(_) => Err(ServerError("could not parse data"))
-                    }
-                }
-                Err(e) => {
-                    println!("error : {e}");
-                    Err(NetworkError(e))
-                }
-            }
-        } else {
-            Err(NetworkError(send.err().unwrap()))
+    pub fn add_room(&mut self, room_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::AddRoom(String::from(room_name))).map(|_| true)
+    }
+
+    pub fn remove_room(&mut self, room_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::RemoveRoom(String::from(room_name))).map(|_| true)
+    }
+
+    pub fn get_devices(&mut self, room_name: &str) -> Result<Vec<String>, SmartHouseError> {
+        match self.request(Command::GetDevices(String::from(room_name)))? {
+            Response::Devices(devices) => Ok(devices),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn add_device(&mut self, room_name: &str, device_kind: &str, config: DeviceConfig)
+        -> Result<bool, SmartHouseError>
+    {
+        let command = Command::AddDevice(String::from(room_name), String::from(device_kind), config);
+        self.request(command).map(|_| true)
+    }
+
+    pub fn remove_device(&mut self, room_name: &str, device_name: &str)
+        -> Result<bool, SmartHouseError>
+    {
+        let command = Command::RemoveDevice(String::from(room_name), String::from(device_name));
+        self.request(command).map(|_| true)
+    }
+
+    // нет показаний или датчики отключились - ошибка `ErrorCode::NoData`
+    pub fn get_thermo_data(&mut self) -> Result<TemperatureStatus, SmartHouseError> {
+        match self.request(Command::GetThermoData)? {
+            Response::TemperatureStatus(status) => Ok(status),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn get_temperature(&mut self, room_name: &str, device_name: &str)
+        -> Result<TemperatureStatus, SmartHouseError>
+    {
+        let command = Command::GetTemperature(String::from(room_name), String::from(device_name));
+        match self.request(command)? {
+            Response::TemperatureStatus(status) => Ok(status),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn get_room_temperature(&mut self, room_name: &str) -> Result<f32, SmartHouseError> {
+        match self.request(Command::GetRoomTemperature(String::from(room_name)))? {
+            Response::Temperature(temperature) => Ok(temperature),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn get_sensors(&mut self) -> Result<Vec<SensorStatus>, SmartHouseError> {
+        match self.request(Command::GetSensors)? {
+            Response::Sensors(sensors) => Ok(sensors),
+            _ => Err(ServerError("unexpected response"))
         }
     }
 
-    fn receive_response(&mut self) -> Result<String, io::Error> {
+    pub fn get_pollers(&mut self) -> Result<Vec<PollerStatus>, SmartHouseError> {
+        match self.request(Command::GetPollers)? {
+            Response::Pollers(pollers) => Ok(pollers),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
 
-        let mut buf = [0; 4];
-        self.stream.read_exact(&mut buf)?;
-        let len = u32::from_be_bytes(buf);
+    pub fn get_history(&mut self, query: HistoryQuery) -> Result<Vec<Bucket>, SmartHouseError> {
+        match self.request(Command::GetHistory(query))? {
+            Response::History(buckets) => Ok(buckets),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
 
-        let mut buf = vec![0; len as _];
-        self.stream.read_exact(&mut buf)?;
-        String::from_utf8(buf)
-            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    pub fn get_report(&mut self) -> Result<HouseReport, SmartHouseError> {
+        match self.request(Command::GetReport)? {
+            Response::Report(report) => Ok(report),
+            _ => Err(ServerError("unexpected response"))
+        }
     }
 
-    fn send_request(&mut self, command: String) -> Result<(), io::Error> {
-        let bytes = command.as_bytes();
-        let len = bytes.len() as u32;
-        let len_bytes = len.to_be_bytes();
-        self.stream.write_all(&len_bytes)?;
-        self.stream.write_all(bytes)?;
-        Ok(())
+    pub fn get_rules(&mut self) -> Result<Vec<Rule>, SmartHouseError> {
+        match self.request(Command::GetRules)? {
+            Response::Rules(rules) => Ok(rules),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn set_rule_enabled(&mut self, rule_name: &str, enabled: bool) -> Result<bool, SmartHouseError> {
+        self.request(Command::SetRuleEnabled(String::from(rule_name), enabled)).map(|_| true)
+    }
+
+    pub fn get_scenes(&mut self) -> Result<Vec<Scene>, SmartHouseError> {
+        match self.request(Command::GetScenes)? {
+            Response::Scenes(scenes) => Ok(scenes),
+            _ => Err(ServerError("unexpected response"))
+        }
     }
-}
\ No newline at end of file
+
+    pub fn add_scene(&mut self, scene: Scene) -> Result<bool, SmartHouseError> {
+        self.request(Command::AddScene(scene)).map(|_| true)
+    }
+
+    pub fn apply_scene(&mut self, scene_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::ApplyScene(String::from(scene_name))).map(|_| true)
+    }
+
+    pub fn get_groups(&mut self) -> Result<Vec<DeviceGroup>, SmartHouseError> {
+        match self.request(Command::GetGroups)? {
+            Response::Groups(groups) => Ok(groups),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn add_group(&mut self, group: DeviceGroup) -> Result<bool, SmartHouseError> {
+        self.request(Command::AddGroup(group)).map(|_| true)
+    }
+
+    pub fn remove_group(&mut self, group_name: &str) -> Result<bool, SmartHouseError> {
+        self.request(Command::RemoveGroup(String::from(group_name))).map(|_| true)
+    }
+
+    pub fn get_jobs(&mut self) -> Result<Vec<Job>, SmartHouseError> {
+        match self.request(Command::GetJobs)? {
+            Response::Jobs(jobs) => Ok(jobs),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    // возвращает id нового задания
+    pub fn add_job(&mut self, room_name: &str, device_name: &str, state: bool, schedule: Schedule)
+        -> Result<u64, SmartHouseError>
+    {
+        let command = Command::AddJob(String::from(room_name), String::from(device_name), state, schedule);
+        match self.request(command)? {
+            Response::JobAdded(id) => Ok(id),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn remove_job(&mut self, id: u64) -> Result<bool, SmartHouseError> {
+        self.request(Command::RemoveJob(id)).map(|_| true)
+    }
+
+    // ошибки отдельных устройств возвращаются в их ответах, а не ошибкой всего запроса
+    pub fn switch_group(&mut self, group_name: &str, state: bool) -> Result<Vec<MemberResponse>, SmartHouseError> {
+        match self.request(Command::SwitchGroup(String::from(group_name), state))? {
+            Response::Members(members) => Ok(members),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    pub fn get_group_power(&mut self, group_name: &str) -> Result<Vec<MemberResponse>, SmartHouseError> {
+        match self.request(Command::GetGroupPower(String::from(group_name)))? {
+            Response::Members(members) => Ok(members),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    // соединение переходит в режим подписки: дальше с сервера приходят только события
+    pub fn subscribe(mut self) -> Result<Subscription, SmartHouseError> {
+        match self.request(Command::Subscribe)? {
+            Response::Subscribed => Ok(Subscription { stream: self.stream, closed: false }),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    fn handshake(&mut self) -> Result<u16, SmartHouseError> {
+        let hello = ClientMessage::Hello { versions: SUPPORTED_VERSIONS.to_vec() };
+        self.send_message(&hello)?;
+        match self.receive_message()? {
+            ServerMessage::Hello { version: Some(version) } => Ok(version),
+            ServerMessage::Hello { version: None } =>
+                Err(ProtocolError(ErrorCode::UnsupportedVersion, String::from("no common protocol version"))),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    fn request(&mut self, command: Command) -> Result<Response, SmartHouseError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send_message(&ClientMessage::Request { version: self.version, id, command })?;
+        // синхронный клиент ждёт ответа на каждый запрос, поэтому ответ должен быть на последний
+        match self.receive_message()? {
+            ServerMessage::Response { id: response_id, response, .. } if response_id == id =>
+                response.into_result(),
+            _ => Err(ServerError("unexpected response"))
+        }
+    }
+
+    fn receive_message(&mut self) -> Result<ServerMessage, SmartHouseError> {
+        let buf = read_frame(&mut self.stream).map_err(NetworkError)?;
+        ServerMessage::decode(&buf)
+    }
+
+    fn send_message(&mut self, message: &ClientMessage) -> Result<(), SmartHouseError> {
+        write_frame(&mut self.stream, &message.encode()?).map_err(NetworkError)
+    }
+}
+
+// События дома по подписке. Итератор заканчивается, когда сервер закрывает соединение.
+pub struct Subscription {
+    stream: TcpStream,
+    closed: bool,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<HouseEvent, SmartHouseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.closed {
+            return None;
+        }
+        let buf = match read_frame(&mut self.stream) {
+            Ok(buf) => buf,
+            Err(e) => {
+                self.closed = true;
+                return if e.kind() == ErrorKind::UnexpectedEof { None } else { Some(Err(NetworkError(e))) };
+            }
+        };
+        match ServerMessage::decode(&buf) {
+            Ok(ServerMessage::Event { event }) => Some(Ok(event)),
+            Ok(_) => Some(Err(ServerError("unexpected response"))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
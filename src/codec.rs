@@ -0,0 +1,103 @@
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/*
+    Общий формат кадра для всех клиентов и серверов: 4 байта длины (big-endian),
+    затем сами данные. Кадры длиннее `MAX_FRAME_SIZE` не принимаются и не отправляются.
+ */
+pub const MAX_FRAME_SIZE : usize = 64 * 1024;
+
+const FRAME_TOO_LARGE : &str = "frame is too large";
+
+fn check_len(len: usize) -> Result<(), io::Error> {
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(ErrorKind::InvalidData, FRAME_TOO_LARGE));
+    }
+    Ok(())
+}
+
+/*
+    Длина и данные отправляются одной записью: при двух отдельных записях в TCP
+    алгоритм Нейгла и отложенные ACK добавляют задержку к каждому запросу в соединении.
+ */
+fn encode_frame(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    check_len(data.len())?;
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(data);
+    Ok(frame)
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, data: &[u8]) -> Result<(), io::Error> {
+    writer.write_all(&encode_frame(data)?)?;
+    writer.flush()
+}
+
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    check_len(len)?;
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8])
+    -> Result<(), io::Error>
+{
+    writer.write_all(&encode_frame(data)?).await?;
+    writer.flush().await
+}
+
+pub async fn read_frame_async<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    check_len(len)?;
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+    use crate::codec::*;
+
+    #[test]
+    fn test_sync_frames() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"first").unwrap();
+        write_frame(&mut buf, &[b'x'; 1000]).unwrap();
+        assert_eq!(&buf[..4], &5u32.to_be_bytes());
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(read_frame(&mut cursor).unwrap(), b"first");
+        assert_eq!(read_frame(&mut cursor).unwrap().len(), 1000);
+        assert_eq!(read_frame(&mut cursor).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        let too_large = vec![0; MAX_FRAME_SIZE + 1];
+        assert_eq!(write_frame(&mut Vec::new(), &too_large).unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut cursor = Cursor::new(((MAX_FRAME_SIZE + 1) as u32).to_be_bytes().to_vec());
+        assert_eq!(read_frame(&mut cursor).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_async_frames_compatible_with_sync() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let long = vec![b'y'; 4096];
+        let expected = long.clone();
+        let writer = tokio::spawn(async move {
+            write_frame_async(&mut client, &long).await.unwrap();
+        });
+        assert_eq!(read_frame_async(&mut server).await.unwrap(), expected);
+        writer.await.unwrap();
+
+        let mut sync_encoded = Vec::new();
+        write_frame(&mut sync_encoded, b"from sync").unwrap();
+        let mut reader = Cursor::new(sync_encoded);
+        assert_eq!(read_frame_async(&mut reader).await.unwrap(), b"from sync");
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::automation::read_rules;
+use crate::device_registry::DeviceRegistry;
+use crate::errors::SmartHouseError;
+use crate::errors::SmartHouseError::{SerializationError, StorageError};
+use crate::history::{DEFAULT_HISTORY_RESOLUTION_SECS, DEFAULT_HISTORY_RETENTION_SECS, DEFAULT_POWER_SAMPLE_SECS, History};
+use crate::scheduler::{Clock, Scheduler};
+use crate::sensor::{DEFAULT_OFFLINE_SECS, DEFAULT_STALE_SECS, SensorBinding, SensorTimeouts};
+use crate::smart_house::SmartHouse;
+use crate::storage::{HouseState, RoomState};
+
+const DEFAULT_LISTEN_ADDR : &str = "127.0.0.1:8081";
+const DEFAULT_REMOTE_ADDR : &str = "127.0.0.1:8083";
+const DEFAULT_POOL_SIZE : usize = 4;
+pub const DEFAULT_IDLE_TIMEOUT_SECS : u64 = 60;
+
+/*
+    Конфигурация серверов: адрес, на котором слушаем клиентов, размер пула потоков,
+    адреса удалённых датчиков и начальная раскладка дома. Если задан `storage` и файл
+    уже существует, дом берётся из него, а `house` используется только при первом запуске.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr : String,
+    #[serde(default = "default_pool_size")]
+    pub pool_size : usize,
+    // через сколько секунд без запросов сервер закрывает соединение с клиентом
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs : u64,
+    #[serde(default)]
+    pub remote_addrs : Vec<String>,
+    // какой датчик каким термометром измеряется; показания остальных датчиков получает весь дом
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensors : Vec<SensorBinding>,
+    // через сколько секунд без показаний датчик считается устаревшим и через сколько - отключённым
+    #[serde(default = "default_sensor_stale_secs")]
+    pub sensor_stale_secs : u64,
+    #[serde(default = "default_sensor_offline_secs")]
+    pub sensor_offline_secs : u64,
+    // история хранится интервалами по `history_resolution_secs` за последние `history_retention_secs`
+    #[serde(default = "default_history_resolution_secs")]
+    pub history_resolution_secs : u64,
+    #[serde(default = "default_history_retention_secs")]
+    pub history_retention_secs : u64,
+    // как часто записывать в историю мощность устройств; 0 - не записывать
+    #[serde(default = "default_power_sample_secs")]
+    pub power_sample_secs : u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage : Option<PathBuf>,
+    // JSON файл со списком правил автоматизации
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules : Option<PathBuf>,
+    // JSON файл с заданиями планировщика; сервер дописывает его сам
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule : Option<PathBuf>,
+    pub house : HouseState,
+}
+
+fn default_listen_addr() -> String {
+    String::from(DEFAULT_LISTEN_ADDR)
+}
+
+fn default_pool_size() -> usize {
+    DEFAULT_POOL_SIZE
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    DEFAULT_IDLE_TIMEOUT_SECS
+}
+
+fn default_sensor_stale_secs() -> u64 {
+    DEFAULT_STALE_SECS
+}
+
+fn default_sensor_offline_secs() -> u64 {
+    DEFAULT_OFFLINE_SECS
+}
+
+fn default_history_resolution_secs() -> u64 {
+    DEFAULT_HISTORY_RESOLUTION_SECS
+}
+
+fn default_history_retention_secs() -> u64 {
+    DEFAULT_HISTORY_RETENTION_SECS
+}
+
+fn default_power_sample_secs() -> u64 {
+    DEFAULT_POWER_SAMPLE_SECS
+}
+
+impl ServerConfig {
+    pub fn read(path: &Path) -> Result<Self, SmartHouseError> {
+        let data = fs::read_to_string(path).map_err(StorageError)?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, SmartHouseError> {
+        serde_json::from_str(data).map_err(SerializationError)
+    }
+
+    pub fn build_house(&self) -> Result<SmartHouse, SmartHouseError> {
+        self.build_house_with_registry(DeviceRegistry::default())
+    }
+
+    pub fn build_house_with_registry(&self, registry: DeviceRegistry) -> Result<SmartHouse, SmartHouseError> {
+        let mut smart_house = match &self.storage {
+            Some(path) if path.exists() => SmartHouse::load_with_registry(path, registry)?,
+            _ => SmartHouse::from_state(&self.house, registry)?
+        };
+        smart_house.set_sensor_timeouts(SensorTimeouts {
+            stale_after: Duration::from_secs(self.sensor_stale_secs),
+            offline_after: Duration::from_secs(self.sensor_offline_secs),
+        });
+        smart_house.set_history(History::new(
+            Duration::from_secs(self.history_resolution_secs),
+            Duration::from_secs(self.history_retention_secs),
+        ));
+        for binding in &self.sensors {
+            smart_house.bind_sensor(binding.clone())?;
+        }
+        if let Some(path) = &self.rules {
+            smart_house.set_rules(read_rules(path)?);
+        }
+        Ok(smart_house)
+    }
+
+    // 0 отключает запись мощности в историю
+    pub fn power_sample_interval(&self) -> Option<Duration> {
+        (self.power_sample_secs > 0).then(|| Duration::from_secs(self.power_sample_secs))
+    }
+
+    // без файла заданий планировщик не нужен
+    pub fn build_scheduler(&self, clock: Box<dyn Clock>) -> Result<Option<Scheduler>, SmartHouseError> {
+        self.schedule.as_deref()
+            .map(|path| Scheduler::load(path, clock))
+            .transpose()
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let rooms = ["room1", "room2"].iter()
+            .map(|r| RoomState { name: String::from(*r), devices: vec![] })
+            .collect();
+        ServerConfig {
+            listen_addr: default_listen_addr(),
+            pool_size: DEFAULT_POOL_SIZE,
+            idle_timeout_secs: DEFAULT_IDLE_TIMEOUT_SECS,
+            remote_addrs: vec![String::from(DEFAULT_REMOTE_ADDR)],
+            sensors: vec![],
+            sensor_stale_secs: DEFAULT_STALE_SECS,
+            sensor_offline_secs: DEFAULT_OFFLINE_SECS,
+            history_resolution_secs: DEFAULT_HISTORY_RESOLUTION_SECS,
+            history_retention_secs: DEFAULT_HISTORY_RETENTION_SECS,
+            power_sample_secs: DEFAULT_POWER_SAMPLE_SECS,
+            storage: None,
+            rules: None,
+            schedule: None,
+            house: HouseState { name: String::from("smart_house"), rooms, scenes: vec![], groups: vec![] },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DEFAULT_IDLE_TIMEOUT_SECS, ServerConfig};
+
+    #[test]
+    fn test_parse_config() {
+        let config = ServerConfig::parse(r#"{
+            "listen_addr": "0.0.0.0:9000",
+            "remote_addrs": ["127.0.0.1:8083", "127.0.0.1:8084"],
+            "house": {
+                "name": "cottage",
+                "rooms": [
+                    { "name": "kitchen", "devices": [
+                        { "name": "Kitchen kettle", "kind": "socket", "power": 2000.0 },
+                        { "name": "Thermo", "kind": "thermometer" }
+                    ]},
+                    { "name": "hall" }
+                ]
+            }
+        }"#).expect("error parsing config");
+
+        assert_eq!(config.listen_addr, "0.0.0.0:9000");
+        assert_eq!(config.pool_size, 4);
+        assert_eq!(config.idle_timeout_secs, DEFAULT_IDLE_TIMEOUT_SECS);
+        assert_eq!(config.remote_addrs.len(), 2);
+
+        let mut smart_house = config.build_house().expect("error building house");
+        let mut rooms = smart_house.get_rooms();
+        rooms.sort();
+        assert_eq!(rooms, vec!["hall", "kitchen"]);
+        assert_eq!(smart_house.get_devices("kitchen").unwrap().len(), 2);
+        smart_house.switch_socket("kitchen", "Kitchen kettle", true).expect("error switching socket");
+        assert_eq!(smart_house.get_socket_state("kitchen", "Kitchen kettle").unwrap(), 2000.0);
+
+        let unknown_kind = ServerConfig::parse(r#"{ "house": { "name": "h", "rooms": [
+            { "name": "r", "devices": [{ "name": "d", "kind": "toaster" }] }
+        ]}}"#).unwrap().build_house();
+        assert!(unknown_kind.is_err());
+    }
+}
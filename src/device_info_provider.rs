@@ -1,6 +1,11 @@
 use crate::errors::{DEVICE_ERROR, ROOM_ERROR, SmartHouseError};
 use crate::errors::SmartHouseError::WrongRequestDataError;
 use rand::Rng;
+use std::time::{Instant, SystemTime};
+use crate::device_registry::{DeviceConfig, LAMP_KIND, SIMULATED_THERMOMETER_KIND, SOCKET_KIND,
+                             THERMOMETER_KIND};
+use crate::report::DeviceReport;
+use std::fmt::{Display, Formatter};
 
 pub trait DeviceInfoProvider {
     // todo: метод, возвращающий состояние устройства по имени комнаты и имени устройства
@@ -9,22 +14,253 @@ pub trait DeviceInfoProvider {
 }
 
 
+// Возможности, которые может поддерживать устройство
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Switchable,
+    PowerMetered,
+    TemperatureSensing,
+    Dimmable,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Capability::Switchable => "switchable",
+            Capability::PowerMetered => "power metered",
+            Capability::TemperatureSensing => "temperature sensing",
+            Capability::Dimmable => "dimmable",
+        };
+        write!(f, "{name}")
+    }
+}
+
+pub trait Switchable {
+    fn switch_on_off(&mut self, state: bool);
+    fn is_on(&self) -> bool;
+}
+
+pub trait PowerMetered {
+    // мгновенная мощность, Вт
+    fn get_consumed_power(&mut self) -> f32;
+    // накопленная энергия с момента создания или последнего сброса, Вт*ч
+    fn get_consumed_energy(&mut self) -> f64;
+    fn reset_energy(&mut self);
+    // восстановление показаний счётчика, например после загрузки дома из файла
+    fn set_consumed_energy(&mut self, energy: f64);
+}
+
+// Счётчик энергии: копит потреблённую энергию, пока через устройство идёт мощность
+#[derive(Clone, Debug)]
+pub struct EnergyMeter {
+    energy_wh: f64,
+    last_update: Instant,
+}
+
+impl EnergyMeter {
+    pub fn new() -> Self {
+        Self::starting_at(0.0, Instant::now())
+    }
+
+    pub fn starting_at(energy_wh: f64, now: Instant) -> Self {
+        EnergyMeter { energy_wh, last_update: now }
+    }
+
+    // добавляет энергию, потреблённую с прошлого обновления при мощности `power` (Вт)
+    pub fn update(&mut self, power: f32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.energy_wh += power as f64 * elapsed.as_secs_f64() / 3600.0;
+        self.last_update = now;
+    }
+
+    pub fn get_energy(&self) -> f64 {
+        self.energy_wh
+    }
+
+    pub fn reset(&mut self, now: Instant) {
+        self.set(0.0, now);
+    }
+
+    pub fn set(&mut self, energy_wh: f64, now: Instant) {
+        self.energy_wh = energy_wh;
+        self.last_update = now;
+    }
+}
+
+impl Default for EnergyMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait TemperatureSensing {
+    fn get_temperature(&self) -> Option<f32>;
+    // новое значение от удалённого датчика; сенсоры без привязки к нему его игнорируют
+    fn set_remote_temperature(&mut self, temperature: f32);
+    // когда пришло последнее значение от удалённого датчика; `None` у сенсоров, которые меряют сами
+    fn last_updated(&self) -> Option<SystemTime> { None }
+}
+
+pub trait Dimmable {
+    // яркость в процентах, 0..=100
+    fn set_brightness(&mut self, brightness: u8);
+    fn get_brightness(&self) -> u8;
+}
+
+/*
+    Устройство обязано иметь только имя. Остальное поведение устройство объявляет через
+    возможности: если метод `as_*` вернул `None`, устройство эту возможность не поддерживает.
+ */
 pub trait Device : Send + Sync {
     fn get_name(&self) -> &str;
     fn set_name(&mut self, name: &str);
-    fn get_consumed_power(&mut self, name: &str) -> f32;
-    fn switch_on_off(&mut self, state: bool);
+    // вид устройства, под которым его фабрика зарегистрирована в `DeviceRegistry`
+    fn get_kind(&self) -> &str;
+
+    // параметры, из которых фабрика может заново создать такое же устройство
+    fn get_config(&self) -> DeviceConfig {
+        let mut config = DeviceConfig::new(self.get_name());
+        config.is_on = self.as_switchable().map(|s| s.is_on()).unwrap_or(false);
+        config
+    }
+
+    fn as_switchable(&self) -> Option<&dyn Switchable> { None }
+    fn as_switchable_mut(&mut self) -> Option<&mut dyn Switchable> { None }
+    fn as_power_metered_mut(&mut self) -> Option<&mut dyn PowerMetered> { None }
+    fn as_temperature_sensing(&self) -> Option<&dyn TemperatureSensing> { None }
+    fn as_temperature_sensing_mut(&mut self) -> Option<&mut dyn TemperatureSensing> { None }
+    fn as_dimmable(&self) -> Option<&dyn Dimmable> { None }
+    fn as_dimmable_mut(&mut self) -> Option<&mut dyn Dimmable> { None }
+
+    fn get_capabilities(&mut self) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        if self.as_switchable().is_some() { capabilities.push(Capability::Switchable) }
+        if self.as_power_metered_mut().is_some() { capabilities.push(Capability::PowerMetered) }
+        if self.as_temperature_sensing().is_some() { capabilities.push(Capability::TemperatureSensing) }
+        if self.as_dimmable().is_some() { capabilities.push(Capability::Dimmable) }
+        capabilities
+    }
+
+    fn get_state(&self) -> String {
+        let mut state = Vec::new();
+        if let Some(switchable) = self.as_switchable() {
+            state.push(String::from(if switchable.is_on() { "on" } else { "off" }));
+        }
+        if let Some(dimmable) = self.as_dimmable() {
+            state.push(format!("brightness {}%", dimmable.get_brightness()));
+        }
+        if let Some(sensing) = self.as_temperature_sensing() {
+            match sensing.get_temperature() {
+                Some(temperature) => state.push(format!("temperature {temperature:.1}")),
+                None => state.push(String::from("no temperature data"))
+            }
+        }
+        format!("{}: {}", self.get_name(), state.join(", "))
+    }
+
+    fn get_report(&mut self) -> DeviceReport {
+        DeviceReport {
+            name: String::from(self.get_name()),
+            is_on: self.as_switchable().map(|s| s.is_on()),
+            consumed_power: self.as_power_metered_mut().map(|p| p.get_consumed_power()),
+            consumed_energy: self.as_power_metered_mut().map(|p| p.get_consumed_energy()),
+            temperature: self.as_temperature_sensing().and_then(|t| t.get_temperature()),
+            last_seen: self.as_temperature_sensing().and_then(|t| t.last_updated()),
+            // зависит от настроек дома, заполняется при составлении отчёта о доме
+            health: None,
+        }
+    }
 }
 
 // Пользовательские устройства:
 pub struct SmartSocket {
     pub(crate) is_on: bool,
-    pub name : String
+    pub name : String,
+    // мощность подключённой нагрузки, Вт
+    pub(crate) power: f32,
+    pub(crate) meter: EnergyMeter,
 }
 
 pub struct SmartThermometer {
+    pub name : String,
+    pub(crate) source: TemperatureSource,
+    pub(crate) temperature: Option<f32>,
+    pub(crate) updated_at: Option<SystemTime>,
+    pub(crate) meter: EnergyMeter,
+}
+
+pub struct SmartLamp {
     pub(crate) is_on: bool,
-    pub name : String
+    pub name : String,
+    pub(crate) brightness: u8,
+    pub(crate) max_power: f32,
+    pub(crate) meter: EnergyMeter,
+}
+
+// Откуда термометр берёт показания
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemperatureSource {
+    // последнее значение, полученное от удалённого UDP датчика
+    Remote,
+    // случайное значение в диапазоне [min, max); пустой или бесконечный диапазон даёт `min`
+    Simulated { min: f32, max: f32 },
+}
+
+// потребление термометра, он работает постоянно
+pub const THERMOMETER_POWER : f32 = 0.5;
+// потребление лампы на полной яркости
+pub const LAMP_MAX_POWER : f32 = 10.0;
+// мощность нагрузки розетки, если она не задана явно
+pub const SOCKET_DEFAULT_POWER : f32 = 10.0;
+
+impl SmartSocket {
+    pub fn new(name : &str) -> Self {
+        Self::with_power(name, SOCKET_DEFAULT_POWER)
+    }
+
+    pub fn with_power(name : &str, power : f32) -> Self {
+        SmartSocket { is_on: false, name: String::from(name), power, meter: EnergyMeter::new() }
+    }
+
+    fn current_power(&self) -> f32 {
+        if self.is_on { self.power } else { 0.0 }
+    }
+}
+
+impl SmartThermometer {
+    pub fn new(name : &str, source : TemperatureSource) -> Self {
+        SmartThermometer {
+            name: String::from(name),
+            source,
+            temperature: None,
+            updated_at: None,
+            meter: EnergyMeter::new()
+        }
+    }
+
+    pub fn get_source(&self) -> TemperatureSource {
+        self.source
+    }
+}
+
+impl SmartLamp {
+    pub fn new(name : &str) -> Self {
+        Self::with_power(name, LAMP_MAX_POWER)
+    }
+
+    pub fn with_power(name : &str, max_power : f32) -> Self {
+        SmartLamp {
+            is_on: false,
+            name: String::from(name),
+            brightness: 100,
+            max_power,
+            meter: EnergyMeter::new()
+        }
+    }
+
+    fn current_power(&self) -> f32 {
+        if self.is_on { self.max_power * self.brightness as f32 / 100.0 } else { 0.0 }
+    }
 }
 
 impl Device for SmartSocket {
@@ -37,13 +273,47 @@ impl Device for SmartSocket {
         self.name = String::from(name);
     }
 
-    fn get_consumed_power(&mut self, name: &str) -> f32 {
-        rand::thread_rng().gen_range(5f32..10f32)
+    fn get_kind(&self) -> &str {
+        SOCKET_KIND
+    }
+
+    fn get_config(&self) -> DeviceConfig {
+        DeviceConfig { name: self.name.clone(), is_on: self.is_on, power: Some(self.power) }
     }
 
+    fn as_switchable(&self) -> Option<&dyn Switchable> { Some(self) }
+    fn as_switchable_mut(&mut self) -> Option<&mut dyn Switchable> { Some(self) }
+    fn as_power_metered_mut(&mut self) -> Option<&mut dyn PowerMetered> { Some(self) }
+}
+
+impl Switchable for SmartSocket {
     fn switch_on_off(&mut self, is_on: bool) {
+        self.meter.update(self.current_power(), Instant::now());
         self.is_on = is_on;
     }
+
+    fn is_on(&self) -> bool {
+        self.is_on
+    }
+}
+
+impl PowerMetered for SmartSocket {
+    fn get_consumed_power(&mut self) -> f32 {
+        self.current_power()
+    }
+
+    fn get_consumed_energy(&mut self) -> f64 {
+        self.meter.update(self.current_power(), Instant::now());
+        self.meter.get_energy()
+    }
+
+    fn reset_energy(&mut self) {
+        self.meter.reset(Instant::now());
+    }
+
+    fn set_consumed_energy(&mut self, energy: f64) {
+        self.meter.set(energy, Instant::now());
+    }
 }
 
 impl Device for SmartThermometer {
@@ -56,13 +326,124 @@ impl Device for SmartThermometer {
         self.name = String::from(name);
     }
 
-    fn get_consumed_power(&mut self, name: &str) -> f32 {
-        todo!()
+    fn get_kind(&self) -> &str {
+        match self.source {
+            TemperatureSource::Remote => THERMOMETER_KIND,
+            TemperatureSource::Simulated { .. } => SIMULATED_THERMOMETER_KIND,
+        }
+    }
+
+    fn as_power_metered_mut(&mut self) -> Option<&mut dyn PowerMetered> { Some(self) }
+    fn as_temperature_sensing(&self) -> Option<&dyn TemperatureSensing> { Some(self) }
+    fn as_temperature_sensing_mut(&mut self) -> Option<&mut dyn TemperatureSensing> { Some(self) }
+}
+
+impl PowerMetered for SmartThermometer {
+    fn get_consumed_power(&mut self) -> f32 {
+        THERMOMETER_POWER
+    }
+
+    fn get_consumed_energy(&mut self) -> f64 {
+        self.meter.update(THERMOMETER_POWER, Instant::now());
+        self.meter.get_energy()
+    }
+
+    fn reset_energy(&mut self) {
+        self.meter.reset(Instant::now());
+    }
+
+    fn set_consumed_energy(&mut self, energy: f64) {
+        self.meter.set(energy, Instant::now());
+    }
+}
+
+impl TemperatureSensing for SmartThermometer {
+    fn get_temperature(&self) -> Option<f32> {
+        match self.source {
+            TemperatureSource::Remote => self.temperature,
+            // gen_range паникует на пустом диапазоне и на диапазоне, ширина которого не конечна
+            TemperatureSource::Simulated { min, max } if min < max && (max - min).is_finite() =>
+                Some(rand::thread_rng().gen_range(min..max)),
+            TemperatureSource::Simulated { min, .. } => Some(min),
+        }
+    }
+
+    fn set_remote_temperature(&mut self, temperature: f32) {
+        if self.source == TemperatureSource::Remote {
+            self.temperature = Some(temperature);
+            self.updated_at = Some(SystemTime::now());
+        }
+    }
+
+    fn last_updated(&self) -> Option<SystemTime> {
+        self.updated_at
+    }
+}
+
+impl Device for SmartLamp {
+
+    fn get_name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    fn get_kind(&self) -> &str {
+        LAMP_KIND
+    }
+
+    fn get_config(&self) -> DeviceConfig {
+        DeviceConfig { name: self.name.clone(), is_on: self.is_on, power: Some(self.max_power) }
     }
 
+    fn as_switchable(&self) -> Option<&dyn Switchable> { Some(self) }
+    fn as_switchable_mut(&mut self) -> Option<&mut dyn Switchable> { Some(self) }
+    fn as_power_metered_mut(&mut self) -> Option<&mut dyn PowerMetered> { Some(self) }
+    fn as_dimmable(&self) -> Option<&dyn Dimmable> { Some(self) }
+    fn as_dimmable_mut(&mut self) -> Option<&mut dyn Dimmable> { Some(self) }
+}
+
+impl Switchable for SmartLamp {
     fn switch_on_off(&mut self, state: bool) {
+        self.meter.update(self.current_power(), Instant::now());
         self.is_on = state;
     }
+
+    fn is_on(&self) -> bool {
+        self.is_on
+    }
+}
+
+impl PowerMetered for SmartLamp {
+    fn get_consumed_power(&mut self) -> f32 {
+        self.current_power()
+    }
+
+    fn get_consumed_energy(&mut self) -> f64 {
+        self.meter.update(self.current_power(), Instant::now());
+        self.meter.get_energy()
+    }
+
+    fn reset_energy(&mut self) {
+        self.meter.reset(Instant::now());
+    }
+
+    fn set_consumed_energy(&mut self, energy: f64) {
+        self.meter.set(energy, Instant::now());
+    }
+}
+
+impl Dimmable for SmartLamp {
+    fn set_brightness(&mut self, brightness: u8) {
+        self.meter.update(self.current_power(), Instant::now());
+        self.brightness = brightness.min(100);
+    }
+
+    fn get_brightness(&self) -> u8 {
+        self.brightness
+    }
 }
 
 // Пользовательские поставщики информации об устройствах.
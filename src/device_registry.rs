@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::device_info_provider::{Device, LAMP_MAX_POWER, SmartLamp, SmartSocket, SmartThermometer,
+                                  SOCKET_DEFAULT_POWER, TemperatureSource};
+use crate::errors::{DEVICE_KIND_ERROR, SmartHouseError};
+use crate::errors::SmartHouseError::WrongRequestDataError;
+
+pub const SOCKET_KIND : &str = "socket";
+pub const THERMOMETER_KIND : &str = "thermometer";
+pub const SIMULATED_THERMOMETER_KIND : &str = "simulated_thermometer";
+pub const LAMP_KIND : &str = "lamp";
+
+const SIMULATED_MIN_TEMPERATURE : f32 = 20.0;
+const SIMULATED_MAX_TEMPERATURE : f32 = 25.0;
+
+// Параметры, с которыми фабрика создаёт устройство.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub name : String,
+    #[serde(default)]
+    pub is_on : bool,
+    // номинальная мощность, Вт; если не задана - берётся значение по умолчанию для вида устройства
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power : Option<f32>,
+}
+
+impl DeviceConfig {
+    pub fn new(name : &str) -> Self {
+        DeviceConfig { name: String::from(name), is_on: false, power: None }
+    }
+}
+
+pub type DeviceFactory = Box<dyn Fn(&DeviceConfig) -> Box<dyn Device> + Send + Sync>;
+
+// Реестр фабрик устройств по виду устройства ("socket", "thermometer", ...).
+pub struct DeviceRegistry {
+    factories: HashMap<String, DeviceFactory>,
+}
+
+impl DeviceRegistry {
+
+    // Пустой реестр без единого вида устройств.
+    pub fn empty() -> Self {
+        DeviceRegistry { factories: HashMap::new() }
+    }
+
+    pub fn register<F>(&mut self, kind: &str, factory: F)
+        where
+            F: Fn(&DeviceConfig) -> Box<dyn Device> + Send + Sync + 'static,
+    {
+        self.factories.insert(String::from(kind), Box::new(factory));
+    }
+
+    pub fn get_kinds(&self) -> Vec<&str> {
+        self.factories.keys().map(|k| k.as_str()).collect()
+    }
+
+    pub fn contains(&self, kind: &str) -> bool {
+        self.factories.contains_key(kind)
+    }
+
+    pub fn create(&self, kind: &str, config: &DeviceConfig)
+        -> Result<Box<dyn Device>, SmartHouseError>
+    {
+        match self.factories.get(kind) {
+            Some(factory) => Ok(factory(config)),
+            None => Err(WrongRequestDataError(DEVICE_KIND_ERROR))
+        }
+    }
+}
+
+impl Default for DeviceRegistry {
+
+    // Реестр со встроенными видами: розеткой, термометром удалённого датчика,
+    // имитацией термометра и лампой.
+    fn default() -> Self {
+        let mut registry = DeviceRegistry::empty();
+        registry.register(SOCKET_KIND, |config| {
+            let mut socket = SmartSocket::with_power(&config.name,
+                                                     config.power.unwrap_or(SOCKET_DEFAULT_POWER));
+            socket.is_on = config.is_on;
+            Box::new(socket)
+        });
+        registry.register(THERMOMETER_KIND, |config| {
+            Box::new(SmartThermometer::new(&config.name, TemperatureSource::Remote))
+        });
+        registry.register(SIMULATED_THERMOMETER_KIND, |config| {
+            let source = TemperatureSource::Simulated {
+                min: SIMULATED_MIN_TEMPERATURE,
+                max: SIMULATED_MAX_TEMPERATURE
+            };
+            Box::new(SmartThermometer::new(&config.name, source))
+        });
+        registry.register(LAMP_KIND, |config| {
+            let mut lamp = SmartLamp::with_power(&config.name,
+                                                 config.power.unwrap_or(LAMP_MAX_POWER));
+            lamp.is_on = config.is_on;
+            Box::new(lamp)
+        });
+        registry
+    }
+}
@@ -3,31 +3,63 @@ use std::fmt::{Display, Formatter};
 use std::io;
 
 use thiserror::Error;
+use crate::device_info_provider::Capability;
+use crate::protocol::ErrorCode;
 
 pub const ROOM_ERROR : &str = "no such room";
+pub const ROOM_EXISTS_ERROR : &str = "room already exists";
 pub const DEVICE_ERROR : &str = "no such device";
+pub const DEVICE_KIND_ERROR : &str = "unknown device kind";
+pub const NO_TEMPERATURE_ERROR : &str = "no temperature data";
+pub const SENSOR_OFFLINE_ERROR : &str = "sensor is offline";
+pub const COMMAND_ERROR : &str = "unknown command";
+pub const RULE_ERROR : &str = "no such rule";
+pub const GROUP_ERROR : &str = "no such group";
+pub const SCENE_ERROR : &str = "no such scene";
+pub const SCHEDULE_ERROR : &str = "invalid schedule";
+pub const JOB_ERROR : &str = "no such job";
+pub const NO_SCHEDULER_ERROR : &str = "scheduler is not configured";
+pub const HISTORY_RANGE_ERROR : &str = "invalid history range";
 
 #[derive(Error, Debug)]
 pub enum SmartHouseError {
     NetworkError(#[from] io::Error),
     WrongRequestDataError(&'static str),
     CommandError(#[from] DeviceError),
-    ServerError(&'static str)
+    ServerError(&'static str),
+    ProtocolError(ErrorCode, String),
+    StorageError(io::Error),
+    SerializationError(serde_json::Error),
 }
 
 #[derive(Debug, Error)]
 pub enum DeviceError {
-    SocketError(&'static str), ThermoError(&'static str),
+    SocketError(&'static str), ThermoError(&'static str), UnsupportedError(Capability),
 }
 
 impl Display for DeviceError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CommandError :{}", self.source().unwrap().to_string())
+        match self {
+            DeviceError::SocketError(msg) | DeviceError::ThermoError(msg) =>
+                write!(f, "CommandError :{msg}"),
+            DeviceError::UnsupportedError(capability) =>
+                write!(f, "CommandError :device is not {capability}")
+        }
     }
 }
 
 impl Display for SmartHouseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "SmartHouseError :{}", self.source().unwrap().to_string())
+        match self.source() {
+            Some(source) => write!(f, "SmartHouseError :{source}"),
+            None => match self {
+                SmartHouseError::WrongRequestDataError(msg) | SmartHouseError::ServerError(msg) =>
+                    write!(f, "SmartHouseError :{msg}"),
+                SmartHouseError::ProtocolError(code, msg) => write!(f, "SmartHouseError :{code:?} {msg}"),
+                SmartHouseError::StorageError(e) => write!(f, "SmartHouseError :{e}"),
+                SmartHouseError::SerializationError(e) => write!(f, "SmartHouseError :{e}"),
+                _ => write!(f, "SmartHouseError")
+            }
+        }
     }
 }
@@ -0,0 +1,103 @@
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use crate::storage::{DeviceState, RoomState};
+
+/*
+    Изменение в доме. Для изменённых значений событие хранит состояние до и после,
+    для добавленных и удалённых комнат и устройств - их снимок.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HouseChange {
+    RoomAdded { room : String },
+    RoomRemoved { room : String, before : RoomState },
+    DeviceAdded { room : String, after : DeviceState },
+    DeviceRemoved { room : String, before : DeviceState },
+    SocketSwitched { room : String, device : String, before : bool, after : bool },
+    BrightnessChanged { room : String, device : String, before : u8, after : u8 },
+    // накопленная до сброса энергия, Вт*ч
+    EnergyReset { room : String, device : String, before : f64 },
+    // новое значение от удалённых датчиков; повтор того же значения событием не считается
+    ThermoDataChanged { before : f32, after : f32 },
+    // новое показание привязанного к термометру датчика
+    TemperatureChanged { room : String, device : String, before : Option<f32>, after : f32 },
+    // правило автоматизации изменило дом; сами изменения приходят отдельными событиями перед этим
+    RuleTriggered { rule : String },
+    // сцена применена целиком; переключения её устройств приходят отдельными событиями перед этим
+    SceneApplied { scene : String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HouseEvent {
+    pub timestamp : SystemTime,
+    pub change : HouseChange,
+}
+
+impl HouseEvent {
+    pub fn new(change: HouseChange) -> Self {
+        HouseEvent { timestamp: SystemTime::now(), change }
+    }
+}
+
+/*
+    Сколько событий может ждать отправки одному подписчику. Подписчик, который не читает
+    соединение и отстал сильнее, отписывается, а не копит события в памяти сервера.
+ */
+pub const SUBSCRIBER_QUEUE_SIZE : usize = 1024;
+
+pub type EventListener = Box<dyn FnMut(&HouseEvent) -> bool + Send>;
+
+// Идентификатор подписчика, по которому его можно отписать.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ListenerId(u64);
+
+/*
+    Шина событий дома. Подписчики вызываются по порядку подписки, синхронно, в том же
+    потоке, что и изменение. Подписчик, вернувший `false`, удаляется и больше не вызывается.
+    Номер ревизии растёт с каждым изменением, даже если подписчиков нет.
+ */
+#[derive(Default)]
+pub struct EventBus {
+    listeners : Vec<(ListenerId, EventListener)>,
+    next_id : u64,
+    revision : u64,
+}
+
+impl EventBus {
+    pub fn subscribe<F>(&mut self, listener: F) -> ListenerId
+        where
+            F: FnMut(&HouseEvent) -> bool + Send + 'static,
+    {
+        let id = ListenerId(self.next_id);
+        self.next_id += 1;
+        self.listeners.push((id, Box::new(listener)));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        let len = self.listeners.len();
+        self.listeners.retain(|(listener_id, _)| *listener_id != id);
+        self.listeners.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    // изменение, о котором подписчикам не сообщается отдельным событием
+    pub fn mark_changed(&mut self) {
+        self.revision += 1;
+    }
+
+    pub fn get_revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn publish(&mut self, change: HouseChange) {
+        self.revision += 1;
+        if self.listeners.is_empty() {
+            return;
+        }
+        let event = HouseEvent::new(change);
+        self.listeners.retain_mut(|(_, listener)| listener(&event));
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Deserialize, Serialize};
+use crate::errors::SmartHouseError;
+
+// результат групповой команды для одного устройства: комната, устройство, результат
+pub type MemberResult<T> = (String, String, Result<T, SmartHouseError>);
+
+/*
+    Группа устройств из разных комнат, например "все обогреватели" или "развлечения".
+    Группа только ссылается на устройства: при удалении устройства или комнаты
+    дом сам убирает их из групп.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceGroup {
+    pub name : String,
+    // комната -> устройства этой комнаты в группе
+    #[serde(default)]
+    pub members : BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DeviceGroup {
+    pub fn new(name: &str) -> Self {
+        DeviceGroup { name: String::from(name), members: BTreeMap::new() }
+    }
+
+    pub fn with_member(mut self, room: &str, device: &str) -> Self {
+        self.members.entry(String::from(room))
+            .or_default()
+            .insert(String::from(device));
+        self
+    }
+
+    // пары (комната, устройство) в стабильном порядке
+    pub fn get_members(&self) -> Vec<(&str, &str)> {
+        self.members.iter()
+            .flat_map(|(room, devices)| devices.iter()
+                .map(move |device| (room.as_str(), device.as_str())))
+            .collect()
+    }
+
+    pub fn remove_member(&mut self, room: &str, device: &str) -> bool {
+        let Some(devices) = self.members.get_mut(room) else {
+            return false;
+        };
+        let removed = devices.remove(device);
+        if devices.is_empty() {
+            self.members.remove(room);
+        }
+        removed
+    }
+
+    pub fn remove_room(&mut self, room: &str) -> bool {
+        self.members.remove(room).is_some()
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::errors::{HISTORY_RANGE_ERROR, SmartHouseError};
+use crate::errors::SmartHouseError::WrongRequestDataError;
+
+pub const DEFAULT_HISTORY_RESOLUTION_SECS : u64 = 60;
+pub const DEFAULT_HISTORY_RETENTION_SECS : u64 = 24 * 60 * 60;
+pub const DEFAULT_POWER_SAMPLE_SECS : u64 = 60;
+// столько корзин с запасом помещается в один кадр ответа
+pub const MAX_HISTORY_BUCKETS : u64 = 360;
+
+// Чьи значения записываются в историю.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SeriesKey {
+    // температура от удалённого датчика, общее значение записывается как `LEGACY_SENSOR_ID`
+    Sensor(u32),
+    // мощность устройства, которое её потребляет
+    Power { room : String, device : String },
+}
+
+// Значения за интервал от `start` длиной в шаг запроса.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub start : SystemTime,
+    pub min : f32,
+    pub max : f32,
+    pub avg : f32,
+    pub count : u32,
+}
+
+/*
+    Запрос истории за [from, to]. Шаг округляется вверх до разрешения истории, корзины
+    выровнены по началу эпохи, поэтому первая корзина может начинаться раньше `from`.
+    Если с запрошенным шагом корзин получилось бы больше `MAX_HISTORY_BUCKETS`, шаг увеличивается:
+    за сутки при разрешении в минуту приходят пятиминутные корзины.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    pub series : SeriesKey,
+    pub from : SystemTime,
+    pub to : SystemTime,
+    #[serde(default)]
+    pub step_secs : u64,
+}
+
+impl HistoryQuery {
+    pub fn new(series: SeriesKey, from: SystemTime, to: SystemTime, step: Duration) -> Self {
+        HistoryQuery { series, from, to, step_secs: step.as_secs() }
+    }
+
+    // последние `period` до текущего момента, например сутки для графика
+    pub fn last(series: SeriesKey, period: Duration, step: Duration) -> Self {
+        let to = SystemTime::now();
+        let from = to.checked_sub(period).unwrap_or(UNIX_EPOCH);
+        Self::new(series, from, to, step)
+    }
+}
+
+// накопленные значения одного интервала хранения, `start` - секунды от начала эпохи
+#[derive(Clone, Debug)]
+struct Aggregate {
+    start : u64,
+    min : f32,
+    max : f32,
+    sum : f64,
+    count : u32,
+}
+
+impl Aggregate {
+    fn new(start: u64, value: f32) -> Self {
+        Aggregate { start, min: value, max: value, sum: value as f64, count: 1 }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.merge(&Aggregate::new(self.start, value));
+    }
+
+    fn merge(&mut self, other: &Aggregate) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    fn to_bucket(&self) -> Bucket {
+        Bucket {
+            start: UNIX_EPOCH + Duration::from_secs(self.start),
+            min: self.min,
+            max: self.max,
+            avg: (self.sum / self.count as f64) as f32,
+            count: self.count,
+        }
+    }
+}
+
+/*
+    История значений в памяти. Значения не хранятся по одному: каждый интервал в `resolution`
+    сворачивается в минимум, максимум и среднее, а интервалы старше `retention` от самого
+    нового значения ряда выбрасываются. Так ряд занимает не больше retention / resolution
+    интервалов, сколько бы значений ни приходило.
+ */
+pub struct History {
+    resolution : u64,
+    retention : u64,
+    series : HashMap<SeriesKey, VecDeque<Aggregate>>,
+}
+
+impl History {
+    pub fn new(resolution: Duration, retention: Duration) -> Self {
+        History { resolution: resolution.as_secs().max(1), retention: retention.as_secs(), series: HashMap::new() }
+    }
+
+    pub fn record(&mut self, series: SeriesKey, at: SystemTime, value: f32) {
+        // NaN испортил бы минимум и максимум всего интервала
+        if !value.is_finite() {
+            return;
+        }
+        let secs = secs_since_epoch(at);
+        let start = secs - secs % self.resolution;
+        let aggregates = self.series.entry(series).or_default();
+        // значения почти всегда приходят по порядку, поиск с конца заканчивается сразу
+        match aggregates.iter().rposition(|a| a.start <= start) {
+            Some(i) if aggregates[i].start == start => aggregates[i].add(value),
+            Some(i) => aggregates.insert(i + 1, Aggregate::new(start, value)),
+            None => aggregates.push_front(Aggregate::new(start, value)),
+        }
+        let newest = aggregates.back().map_or(start, |a| a.start);
+        // при огромном `retention` интервал не устаревает никогда
+        while aggregates.front().is_some_and(|a| a.start.checked_add(self.retention).is_some_and(|end| end <= newest)) {
+            aggregates.pop_front();
+        }
+    }
+
+    // у ряда, в который ещё ничего не записано, история пустая
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<Bucket>, SmartHouseError> {
+        if query.from > query.to {
+            return Err(WrongRequestDataError(HISTORY_RANGE_ERROR));
+        }
+        let (from, to) = (secs_since_epoch(query.from), secs_since_epoch(query.to));
+        // корзины начинаются не раньше from - resolution, так их не больше MAX_HISTORY_BUCKETS
+        let min_step = (to - from).saturating_add(self.resolution).div_ceil(MAX_HISTORY_BUCKETS - 1);
+        // шаг от клиента может быть любым, переполнение - ошибка запроса, а не паника под блокировкой дома
+        let step = query.step_secs.max(min_step).max(1).div_ceil(self.resolution)
+            .checked_mul(self.resolution)
+            .ok_or(WrongRequestDataError(HISTORY_RANGE_ERROR))?;
+        let mut buckets: Vec<Aggregate> = Vec::new();
+        let aggregates = self.series.get(&query.series).into_iter()
+            .flatten()
+            .filter(|a| a.start.saturating_add(self.resolution) > from && a.start <= to);
+        for aggregate in aggregates {
+            let start = aggregate.start - aggregate.start % step;
+            match buckets.last_mut() {
+                Some(bucket) if bucket.start == start => bucket.merge(aggregate),
+                _ => buckets.push(Aggregate { start, ..aggregate.clone() }),
+            }
+        }
+        Ok(buckets.iter().map(Aggregate::to_bucket).collect())
+    }
+
+    pub fn remove_device(&mut self, room: &str, device: &str) {
+        self.series.retain(|key, _| !matches!(key, SeriesKey::Power { room: r, device: d } if r == room && d == device));
+    }
+
+    pub fn remove_room(&mut self, room: &str) {
+        self.series.retain(|key, _| !matches!(key, SeriesKey::Power { room: r, .. } if r == room));
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(DEFAULT_HISTORY_RESOLUTION_SECS), Duration::from_secs(DEFAULT_HISTORY_RETENTION_SECS))
+    }
+}
+
+fn secs_since_epoch(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use crate::history::{History, HistoryQuery, MAX_HISTORY_BUCKETS, SeriesKey};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn summary(history: &History, series: SeriesKey, from: u64, to: u64, step: u64) -> Vec<(u64, f32, f32, f32, u32)> {
+        let query = HistoryQuery::new(series, at(from), at(to), Duration::from_secs(step));
+        history.query(&query).unwrap().iter()
+            .map(|b| (b.start.duration_since(UNIX_EPOCH).unwrap().as_secs(), b.min, b.max, b.avg, b.count))
+            .collect()
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::new(Duration::from_secs(60), Duration::from_secs(600));
+        let sensor = SeriesKey::Sensor(1);
+        for (secs, value) in [(0, 20.0), (30, 22.0), (61, 18.0), (130, 25.0)] {
+            history.record(sensor.clone(), at(secs), value);
+        }
+        // пришло не по порядку и попало в уже закрытый интервал
+        history.record(sensor.clone(), at(59), 24.0);
+        history.record(sensor.clone(), at(100), f32::NAN);
+
+        assert_eq!(summary(&history, sensor.clone(), 0, 200, 0), vec![
+            (0, 20.0, 24.0, 22.0, 3),
+            (60, 18.0, 18.0, 18.0, 1),
+            (120, 25.0, 25.0, 25.0, 1),
+        ]);
+        // шаг 90 секунд округляется до 120
+        assert_eq!(summary(&history, sensor.clone(), 0, 200, 90), vec![
+            (0, 18.0, 24.0, 21.0, 4),
+            (120, 25.0, 25.0, 25.0, 1),
+        ]);
+        assert_eq!(summary(&history, sensor.clone(), 70, 110, 0), vec![(60, 18.0, 18.0, 18.0, 1)]);
+        assert!(summary(&history, SeriesKey::Sensor(2), 0, 200, 0).is_empty());
+        let reversed = HistoryQuery::new(sensor.clone(), at(200), at(0), Duration::ZERO);
+        assert!(history.query(&reversed).is_err());
+        let huge_step = HistoryQuery { step_secs: u64::MAX, ..HistoryQuery::new(sensor.clone(), at(0), at(200), Duration::ZERO) };
+        assert!(history.query(&huge_step).is_err());
+
+        // интервалы старше 600 секунд от самого нового значения выбрасываются
+        history.record(sensor.clone(), at(700), 21.0);
+        assert_eq!(summary(&history, sensor.clone(), 0, 1000, 0), vec![
+            (120, 25.0, 25.0, 25.0, 1),
+            (660, 21.0, 21.0, 21.0, 1),
+        ]);
+
+        let power = |room: &str, device: &str| SeriesKey::Power { room: String::from(room), device: String::from(device) };
+        history.record(power("hall", "Lamp"), at(0), 60.0);
+        history.record(power("hall", "Socket"), at(0), 1500.0);
+        history.record(power("kitchen", "Kettle"), at(0), 2000.0);
+        history.remove_device("hall", "Lamp");
+        assert!(summary(&history, power("hall", "Lamp"), 0, 60, 0).is_empty());
+        assert_eq!(summary(&history, power("hall", "Socket"), 0, 60, 0).len(), 1);
+        history.remove_room("hall");
+        assert!(summary(&history, power("hall", "Socket"), 0, 60, 0).is_empty());
+        assert_eq!(summary(&history, power("kitchen", "Kettle"), 0, 60, 0).len(), 1);
+    }
+
+    #[test]
+    fn test_history_limits() {
+        // сутки поминутных значений: с шагом в минуту корзин было бы 1440
+        let day = 24 * 60 * 60;
+        let mut history = History::new(Duration::from_secs(60), Duration::from_secs(day));
+        let sensor = SeriesKey::Sensor(1);
+        for minute in 0..day / 60 {
+            history.record(sensor.clone(), at(day + minute * 60), 20.0);
+        }
+        let buckets = summary(&history, sensor.clone(), day, 2 * day, 60);
+        assert!(buckets.len() as u64 <= MAX_HISTORY_BUCKETS);
+        assert_eq!(buckets[1].0 - buckets[0].0, 300);
+        assert_eq!(buckets.iter().map(|b| b.4).sum::<u32>(), 1440);
+
+        // огромный срок хранения не переполняет арифметику при записи
+        let mut history = History::new(Duration::from_secs(60), Duration::from_secs(u64::MAX));
+        history.record(sensor.clone(), at(0), 20.0);
+        history.record(sensor.clone(), at(day), 21.0);
+        assert_eq!(summary(&history, sensor, 0, day, 0).len(), 2);
+    }
+}
@@ -3,6 +3,27 @@
 
 pub mod smart_house;
 pub mod device_info_provider;
+pub mod device_registry;
+pub mod report;
+pub mod storage;
+pub mod config;
+pub mod codec;
+pub mod protocol;
+pub mod events;
+pub mod automation;
+pub mod scene;
+pub mod group;
+pub mod sensor;
+pub mod poller;
+pub mod scheduler;
+pub mod history;
+
+use serde::{Deserialize, Serialize};
+use crate::device_registry::DeviceConfig;
+use crate::group::DeviceGroup;
+use crate::history::HistoryQuery;
+use crate::scene::Scene;
+use crate::scheduler::Schedule;
 pub mod errors;
 pub mod server;
 pub mod client;
@@ -10,18 +31,56 @@ pub mod remote_server;
 pub mod async_server;
 pub mod async_client;
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Command {
     SwitchSocketCommand(String, String, bool),
     GetSocketConsumedPower(String, String),
+    GetSocketConsumedEnergy(String, String),
+    ResetSocketEnergy(String, String),
+    GetRooms,
+    AddRoom(String),
+    RemoveRoom(String),
+    GetDevices(String),
+    // комната, вид устройства и параметры, с которыми его создаёт фабрика
+    AddDevice(String, String, DeviceConfig),
+    RemoveDevice(String, String),
+    // последнее значение, полученное от удалённых датчиков
+    GetThermoData,
+    GetTemperature(String, String),
+    // средняя по термометрам комнаты
+    GetRoomTemperature(String),
+    // привязки удалённых датчиков, их последние показания и состояние
+    GetSensors,
+    // состояние потоков, принимающих показания датчиков
+    GetPollers,
+    // температура датчика или мощность устройства за интервал, свёрнутая в корзины
+    GetHistory(HistoryQuery),
+    GetReport,
+    // после ответа `Subscribed` соединение только присылает события дома
+    Subscribe,
+    GetRules,
+    SetRuleEnabled(String, bool),
+    GetScenes,
+    AddScene(Scene),
+    ApplyScene(String),
+    GetGroups,
+    AddGroup(DeviceGroup),
+    RemoveGroup(String),
+    // групповые команды отвечают результатом для каждого устройства группы
+    SwitchGroup(String, bool),
+    GetGroupPower(String),
+    // задания планировщика; если планировщик не настроен, ответ - ошибка `Unsupported`
+    GetJobs,
+    // комната, розетка, в какое состояние её переключить и когда
+    AddJob(String, String, bool, Schedule),
+    RemoveJob(u64),
 }
 
 const START_MESSAGING_COMMAND : &str = "S_M_C";
 const END_MESSAGING_COMMAND : &str = "E_M_C";
 const SWITCH_SOCKET_COMMAND : &str = "S_S_C";
 const GET_SOCKET_CONSUMED_POWER : &str = "G_S_C_P";
-const ARGUMENTS : &str = "ARGS";
+const GET_SOCKET_CONSUMED_ENERGY : &str = "G_S_C_E";
+const RESET_SOCKET_ENERGY : &str = "R_S_E";
 const OK_RESPONSE: &str = "OK";
 const ERR_RESPONSE: &str = "ERR";
-
-
-
@@ -1,16 +1,17 @@
+use std::env;
+use std::path::Path;
+use smart_house::config::ServerConfig;
 use smart_house::remote_server::RemoteServer;
 use smart_house::server::{Server};
-use smart_house::smart_house::SmartHouse;
 
 fn main() {
-    let addr = "127.0.0.1:8081";
-    let pool_size = 4;
-    let remote_addr: & 'static str = "127.0.0.1:8083";
-
-    let smart_house = SmartHouse::new("smart_house", vec!["room1", "room2"]);
+    // путь к файлу конфигурации можно передать первым аргументом
+    let config = match env::args().nth(1) {
+        Some(path) => ServerConfig::read(Path::new(&path)).expect("could not read config"),
+        None => ServerConfig::default()
+    };
 
     RemoteServer::start();
 
-    let server = Server {smart_house};
-    server.start(addr, pool_size, remote_addr);
+    Server::start_from_config(config).expect("could not start server");
 }
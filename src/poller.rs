@@ -0,0 +1,291 @@
+use std::io;
+use std::io::ErrorKind::*;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+use crate::sensor::{READING_SIZE, SensorReading};
+use crate::smart_house::SmartHouse;
+
+// сколько ждать датаграмму, прежде чем отметить, что датчики молчат
+pub const POLL_READ_TIMEOUT : Duration = Duration::from_secs(5);
+const BACKOFF_INITIAL : Duration = Duration::from_millis(500);
+const BACKOFF_MAX : Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    // датаграммы не было или она испорчена: сокет исправен, ждём следующую
+    Transient,
+    // сокет больше не годится, его нужно открыть заново
+    Rebind,
+    // адрес не откроется никогда, повторять бессмысленно
+    Fatal,
+}
+
+pub fn classify(error: &io::Error) -> ErrorClass {
+    match error.kind() {
+        // ConnectionRefused и ConnectionReset у UDP - это ICMP ответ на нашу прошлую отправку
+        WouldBlock | TimedOut | Interrupted | InvalidData | ConnectionRefused | ConnectionReset =>
+            ErrorClass::Transient,
+        PermissionDenied | AddrNotAvailable | InvalidInput | Unsupported => ErrorClass::Fatal,
+        _ => ErrorClass::Rebind,
+    }
+}
+
+// Задержка перед очередной попыткой: удваивается с каждой неудачей, но не больше `max`.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial : Duration,
+    max : Duration,
+    next : Duration,
+    attempt : u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff { initial, max, next: initial, attempt: 0 }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        self.attempt += 1;
+        delay
+    }
+
+    pub fn get_attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+        self.attempt = 0;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(BACKOFF_INITIAL, BACKOFF_MAX)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PollerState {
+    Binding,
+    // сокет открыт, показания приходят
+    Receiving,
+    // сокет открыт, но за `read_timeout` не пришло ни одной датаграммы
+    Silent,
+    // ждёт перед тем, как открыть сокет заново
+    Backoff { attempt : u32, delay : Duration },
+    // ошибка, которую повтор не исправит; поток опроса завершён
+    Stopped,
+}
+
+// Что видно оператору о потоке опроса одного адреса.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PollerStatus {
+    pub addr : String,
+    pub state : PollerState,
+    pub readings : u64,
+    pub errors : u64,
+    pub rebinds : u64,
+    pub last_reading_at : Option<SystemTime>,
+    pub last_error : Option<String>,
+}
+
+pub type RulesAppliedHook = Box<dyn FnMut(&mut SmartHouse) + Send>;
+
+/*
+    Приём показаний удалённых датчиков на одном UDP адресе. Испорченные датаграммы и
+    молчание датчиков не прерывают приём, при остальных ошибках сокет открывается заново
+    с растущей задержкой, и только ошибки, которые повтор не исправит, останавливают поток.
+    Состояние после каждого изменения публикуется в доме, откуда его получают клиенты.
+ */
+pub struct UdpPoller {
+    pub read_timeout : Duration,
+    pub backoff : Backoff,
+    // вызывается под блокировкой дома, когда показание включило правила; сервер так сохраняет дом
+    pub on_rules_applied : Option<RulesAppliedHook>,
+    status : PollerStatus,
+}
+
+impl UdpPoller {
+    pub fn new(addr: &str) -> Self {
+        let status = PollerStatus {
+            addr: String::from(addr),
+            state: PollerState::Binding,
+            readings: 0,
+            errors: 0,
+            rebinds: 0,
+            last_reading_at: None,
+            last_error: None,
+        };
+        UdpPoller { read_timeout: POLL_READ_TIMEOUT, backoff: Backoff::default(), on_rules_applied: None, status }
+    }
+
+    // возвращает управление, только если адрес открыть невозможно
+    pub fn run(mut self, smart_house: Arc<Mutex<SmartHouse>>) {
+        println!("thread for requesting remote server {} started", self.status.addr);
+        loop {
+            self.status.state = PollerState::Binding;
+            self.publish(&smart_house);
+            let error = match self.bind() {
+                Ok(socket) => self.receive(&socket, &smart_house),
+                Err(e) => e
+            };
+            self.status.errors += 1;
+            self.status.last_error = Some(error.to_string());
+            if classify(&error) == ErrorClass::Fatal {
+                println!("polling {} stopped: {error}", self.status.addr);
+                self.status.state = PollerState::Stopped;
+                self.publish(&smart_house);
+                return;
+            }
+            let delay = self.backoff.next_delay();
+            println!("polling {} failed: {error}, retrying in {delay:?}", self.status.addr);
+            self.status.state = PollerState::Backoff { attempt: self.backoff.get_attempt(), delay };
+            self.publish(&smart_house);
+            thread::sleep(delay);
+            self.status.rebinds += 1;
+        }
+    }
+
+    fn bind(&self) -> Result<UdpSocket, io::Error> {
+        let socket = UdpSocket::bind(&self.status.addr)?;
+        socket.set_read_timeout(Some(self.read_timeout))?;
+        Ok(socket)
+    }
+
+    // принимает показания, пока не случится ошибка, после которой сокет нужно открыть заново
+    fn receive(&mut self, socket: &UdpSocket, smart_house: &Arc<Mutex<SmartHouse>>) -> io::Error {
+        // датаграмма длиннее показания заведомо чужая, её хватит, чтобы это заметить
+        let mut buf = [0u8; READING_SIZE + 1];
+        loop {
+            let received = socket.recv(&mut buf)
+                .and_then(|len| SensorReading::decode(&buf[..len]));
+            let mut house = smart_house.lock().unwrap_or_else(PoisonError::into_inner);
+            match received {
+                Ok(reading) => {
+                    self.backoff.reset();
+                    // показания не по порядку и повторы дом отбрасывает сам
+                    house.set_sensor_reading(reading);
+                    // новое значение температуры может включить правила автоматизации
+                    if !house.apply_rules().is_empty() {
+                        if let Some(on_rules_applied) = self.on_rules_applied.as_mut() {
+                            on_rules_applied(&mut house);
+                        }
+                    }
+                    self.status.state = PollerState::Receiving;
+                    self.status.readings += 1;
+                    self.status.last_reading_at = Some(SystemTime::now());
+                }
+                Err(e) if matches!(e.kind(), WouldBlock | TimedOut) => {
+                    self.status.state = PollerState::Silent;
+                }
+                Err(e) if classify(&e) == ErrorClass::Transient => {
+                    println!("error while receiving remote data: {e}");
+                    self.status.errors += 1;
+                    self.status.last_error = Some(e.to_string());
+                }
+                Err(e) => return e
+            }
+            house.set_poller_status(self.status.clone());
+        }
+    }
+
+    fn publish(&self, smart_house: &Arc<Mutex<SmartHouse>>) {
+        smart_house.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .set_poller_status(self.status.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::UdpSocket;
+    use std::sync::{Arc, mpsc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::automation::{Action, Condition, Rule};
+    use crate::device_registry::{DeviceConfig, SOCKET_KIND};
+    use crate::poller::{Backoff, classify, ErrorClass, PollerState, UdpPoller};
+    use crate::sensor::SensorReading;
+    use crate::smart_house::SmartHouse;
+
+    #[test]
+    fn test_backoff_and_error_classes() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(350));
+        let delays = (0..4).map(|_| backoff.next_delay().as_millis()).collect::<Vec<u128>>();
+        assert_eq!(delays, vec![100, 200, 350, 350]);
+        assert_eq!(backoff.get_attempt(), 4);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+
+        let class = |kind| classify(&io::Error::from(kind));
+        assert_eq!(class(io::ErrorKind::TimedOut), ErrorClass::Transient);
+        assert_eq!(class(io::ErrorKind::InvalidData), ErrorClass::Transient);
+        assert_eq!(class(io::ErrorKind::AddrInUse), ErrorClass::Rebind);
+        assert_eq!(class(io::ErrorKind::NetworkDown), ErrorClass::Rebind);
+        assert_eq!(class(io::ErrorKind::AddrNotAvailable), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn test_poller() {
+        let mut house = SmartHouse::new("SmartHouse", vec!["hall"]);
+        house.add_device("hall", SOCKET_KIND, DeviceConfig::new("Socket_Heater")).unwrap();
+        house.set_rules(vec![Rule {
+            name: String::from("heater on when cold"),
+            enabled: true,
+            condition: Condition::TemperatureBelow(18.0),
+            action: Action::SwitchSocket { room: String::from("hall"), device: String::from("Socket_Heater"), state: true },
+        }]);
+        let smart_house = Arc::new(Mutex::new(house));
+
+        // адрес, который нельзя открыть, останавливает опрос сразу
+        UdpPoller::new("no port").run(smart_house.clone());
+        let statuses = smart_house.lock().unwrap().get_poller_statuses().to_vec();
+        assert_eq!(statuses[0].state, PollerState::Stopped);
+        assert!(statuses[0].last_error.is_some());
+
+        let addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let mut poller = UdpPoller::new(&addr);
+        poller.read_timeout = Duration::from_millis(50);
+        let (saved, saves) = mpsc::channel();
+        poller.on_rules_applied = Some(Box::new(move |house| {
+            saved.send(house.is_switched_on("hall", "Socket_Heater").unwrap()).unwrap();
+        }));
+        let house = smart_house.clone();
+        thread::spawn(move || poller.run(house));
+        let status = || smart_house.lock().unwrap().get_poller_statuses().iter()
+            .find(|s| s.addr == addr)
+            .cloned();
+        let wait_for = |done: &dyn Fn() -> bool| {
+            let started = Instant::now();
+            while !done() && started.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(done());
+        };
+        wait_for(&|| status().is_some_and(|s| s.state == PollerState::Silent));
+
+        // испорченная датаграмма не прерывает приём
+        let sensor = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sensor.send_to(&[1, 2, 3], &addr).unwrap();
+        sensor.send_to(&SensorReading::new(1, 0, 21.5).encode(), &addr).unwrap();
+        wait_for(&|| status().is_some_and(|s| s.readings == 1));
+        let received = status().unwrap();
+        assert_eq!(received.errors, 1);
+        assert_eq!(received.rebinds, 0);
+        assert!(received.last_reading_at.is_some());
+        assert_eq!(smart_house.lock().unwrap().get_thermo_data(), 21.5);
+        wait_for(&|| status().is_some_and(|s| s.state == PollerState::Silent));
+        assert!(saves.try_recv().is_err());
+
+        // показание включило правило: сервер узнаёт об этом уже с изменённым домом
+        sensor.send_to(&SensorReading::new(1, 1, 15.0).encode(), &addr).unwrap();
+        assert_eq!(saves.recv_timeout(Duration::from_secs(5)), Ok(true));
+    }
+}
@@ -0,0 +1,621 @@
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use crate::Command;
+use crate::errors::{COMMAND_ERROR, DEVICE_ERROR, DEVICE_KIND_ERROR, DeviceError, GROUP_ERROR, JOB_ERROR,
+                    NO_SCHEDULER_ERROR, NO_TEMPERATURE_ERROR, ROOM_ERROR, RULE_ERROR, SCENE_ERROR,
+                    SENSOR_OFFLINE_ERROR, SmartHouseError};
+use crate::errors::SmartHouseError::{SerializationError, WrongRequestDataError};
+use crate::automation::Rule;
+use crate::events::HouseEvent;
+use crate::group::{DeviceGroup, MemberResult};
+use crate::history::Bucket;
+use crate::report::HouseReport;
+use crate::scene::Scene;
+use crate::scheduler::Job;
+use crate::poller::PollerStatus;
+use crate::sensor::{SensorStatus, TemperatureStatus};
+use crate::smart_house::SmartHouse;
+
+/*
+    Версии протокола:
+    1 - текстовые команды с маркерами S_M_C / ARGS / E_M_C, без рукопожатия;
+    2 - типизированные сообщения в JSON, соединение начинается с рукопожатия `Hello`.
+    Оба формата передаются в кадрах из `codec`, сервер различает их по первому байту кадра.
+ */
+pub const LEGACY_PROTOCOL_VERSION : u16 = 1;
+pub const PROTOCOL_VERSION : u16 = 2;
+// версии, которые сервер может выбрать при рукопожатии
+pub const SUPPORTED_VERSIONS : [u16; 1] = [PROTOCOL_VERSION];
+
+const ENCODING_ERROR : &str = "request is not valid utf-8";
+const START_COMMAND_ERROR : &str = "wrong start command";
+const ARGUMENTS_ERROR : &str = "wrong command arguments";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    BadRequest,
+    UnsupportedVersion,
+    NoSuchRoom,
+    NoSuchDevice,
+    NoSuchRule,
+    NoSuchScene,
+    NoSuchGroup,
+    NoSuchJob,
+    UnknownDeviceKind,
+    Unsupported,
+    NoData,
+    DeviceFailure,
+    Internal,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Ok,
+    Power(f32),
+    Energy(f64),
+    Temperature(f32),
+    // температура термометра или удалённых датчиков: когда пришло показание и не устарело ли оно
+    TemperatureStatus(TemperatureStatus),
+    Rooms(Vec<String>),
+    Devices(Vec<String>),
+    Report(HouseReport),
+    Subscribed,
+    Rules(Vec<Rule>),
+    Scenes(Vec<Scene>),
+    Groups(Vec<DeviceGroup>),
+    // ответ групповой команды: отдельный ответ для каждого устройства группы
+    Members(Vec<MemberResponse>),
+    Sensors(Vec<SensorStatus>),
+    Pollers(Vec<PollerStatus>),
+    History(Vec<Bucket>),
+    Jobs(Vec<Job>),
+    // id нового задания
+    JobAdded(u64),
+    Error(ErrorCode, String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MemberResponse {
+    pub room : String,
+    pub device : String,
+    pub response : Response,
+}
+
+// Сообщения клиента серверу
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    // версии протокола, которые понимает клиент
+    Hello { versions: Vec<u16> },
+    /*
+        `id` выбирает клиент, сервер возвращает его в ответе, чтобы клиент мог
+        сопоставить ответ с запросом, даже если запросов в полёте несколько.
+     */
+    Request {
+        version: u16,
+        #[serde(default)]
+        id: u64,
+        command: Command,
+    },
+}
+
+// Сообщения сервера клиенту
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    // выбранная сервером версия; `None`, если общих версий нет
+    Hello { version: Option<u16> },
+    Response {
+        version: u16,
+        #[serde(default)]
+        id: u64,
+        response: Response,
+    },
+    // событие для клиента, подписавшегося командой `Subscribe`
+    Event { event: HouseEvent },
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Result<Vec<u8>, SmartHouseError> {
+        serde_json::to_vec(self).map_err(SerializationError)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, SmartHouseError> {
+        serde_json::from_slice(bytes).map_err(SerializationError)
+    }
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Result<Vec<u8>, SmartHouseError> {
+        serde_json::to_vec(self).map_err(SerializationError)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, SmartHouseError> {
+        serde_json::from_slice(bytes).map_err(SerializationError)
+    }
+}
+
+impl Response {
+    pub fn error(code: ErrorCode, message: &str) -> Self {
+        Response::Error(code, String::from(message))
+    }
+
+    // ответ в виде текста протокола первой версии
+    pub fn to_legacy_string(&self) -> String {
+        match self {
+            Response::Ok | Response::Subscribed => String::from(crate::OK_RESPONSE),
+            Response::Power(power) => power.to_string(),
+            Response::Energy(energy) => energy.to_string(),
+            Response::Temperature(temperature) => temperature.to_string(),
+            Response::TemperatureStatus(status) => format!("{} {}", status.temperature, status.health),
+            Response::Rooms(names) | Response::Devices(names) => names.join("\n"),
+            Response::Report(report) => report.to_string(),
+            Response::Rules(rules) => rules.iter()
+                .map(|r| format!("{} {}", r.name, r.enabled))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Response::Scenes(scenes) => scenes.iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            Response::Groups(groups) => groups.iter()
+                .map(|g| g.name.as_str())
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            Response::Members(members) => members.iter()
+                .map(|m| format!("{} {} {}", m.room, m.device, m.response.to_legacy_string()))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Response::Sensors(sensors) => sensors.iter()
+                .map(|s| match &s.last_reading {
+                    Some(reading) => format!("{} {} {}", s.sensor_id, s.health, reading.temperature),
+                    None => format!("{} {}", s.sensor_id, s.health)
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Response::Pollers(pollers) => pollers.iter()
+                .map(|p| format!("{} {:?} readings {} errors {}", p.addr, p.state, p.readings, p.errors))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Response::History(buckets) => buckets.iter()
+                .map(|b| {
+                    let start = b.start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                    format!("{start} min {} max {} avg {} count {}", b.min, b.max, b.avg, b.count)
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Response::Jobs(jobs) => jobs.iter()
+                .map(|j| match j.next_run {
+                    Some(next_run) => format!("{} {} {} {} {next_run}", j.id, j.room, j.device, j.state),
+                    None => format!("{} {} {} {}", j.id, j.room, j.device, j.state),
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Response::JobAdded(id) => id.to_string(),
+            Response::Error(_, message) => format!("{} {message}", crate::ERR_RESPONSE),
+        }
+    }
+
+    // превращает ответ с ошибкой в `SmartHouseError`
+    pub fn into_result(self) -> Result<Response, SmartHouseError> {
+        match self {
+            Response::Error(code, message) => Err(SmartHouseError::ProtocolError(code, message)),
+            response => Ok(response)
+        }
+    }
+}
+
+impl From<&SmartHouseError> for ErrorCode {
+    fn from(error: &SmartHouseError) -> Self {
+        match error {
+            SmartHouseError::WrongRequestDataError(ROOM_ERROR) => ErrorCode::NoSuchRoom,
+            SmartHouseError::WrongRequestDataError(DEVICE_ERROR) => ErrorCode::NoSuchDevice,
+            SmartHouseError::WrongRequestDataError(DEVICE_KIND_ERROR) => ErrorCode::UnknownDeviceKind,
+            SmartHouseError::WrongRequestDataError(RULE_ERROR) => ErrorCode::NoSuchRule,
+            SmartHouseError::WrongRequestDataError(SCENE_ERROR) => ErrorCode::NoSuchScene,
+            SmartHouseError::WrongRequestDataError(GROUP_ERROR) => ErrorCode::NoSuchGroup,
+            SmartHouseError::WrongRequestDataError(JOB_ERROR) => ErrorCode::NoSuchJob,
+            SmartHouseError::WrongRequestDataError(NO_SCHEDULER_ERROR) => ErrorCode::Unsupported,
+            SmartHouseError::WrongRequestDataError(_) => ErrorCode::BadRequest,
+            SmartHouseError::CommandError(DeviceError::UnsupportedError(_)) => ErrorCode::Unsupported,
+            SmartHouseError::CommandError(DeviceError::ThermoError(NO_TEMPERATURE_ERROR)) => ErrorCode::NoData,
+            SmartHouseError::CommandError(DeviceError::ThermoError(SENSOR_OFFLINE_ERROR)) => ErrorCode::NoData,
+            SmartHouseError::CommandError(_) => ErrorCode::DeviceFailure,
+            SmartHouseError::ProtocolError(code, _) => *code,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+impl From<SmartHouseError> for Response {
+    fn from(error: SmartHouseError) -> Self {
+        Response::Error(ErrorCode::from(&error), error.to_string())
+    }
+}
+
+// наибольшая версия, которую понимают и клиент, и сервер
+pub fn negotiate(client_versions: &[u16]) -> Option<u16> {
+    client_versions.iter()
+        .filter(|v| SUPPORTED_VERSIONS.contains(v))
+        .max()
+        .copied()
+}
+
+/*
+    Разбор кадра первой версии. Любая ошибка в кадре (не UTF-8, нет маркера начала,
+    не хватает аргументов, неизвестная команда) возвращается как `WrongRequestDataError`,
+    чтобы сервер мог ответить клиенту, а не упасть.
+ */
+pub fn decode_legacy(bytes: &[u8]) -> Result<Command, SmartHouseError> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| WrongRequestDataError(ENCODING_ERROR))?;
+    let mut lines = text.split('\n');
+
+    if !lines.next().is_some_and(|l| l.contains(crate::START_MESSAGING_COMMAND)) {
+        return Err(WrongRequestDataError(START_COMMAND_ERROR));
+    }
+    let command = lines.next().ok_or(WrongRequestDataError(COMMAND_ERROR))?;
+
+    let args = lines
+        .skip(1)
+        .take_while(|l| *l != crate::END_MESSAGING_COMMAND)
+        .flat_map(|l| l.split(' '))
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<&str>>();
+    let arg = |i: usize| args.get(i)
+        .map(|a| String::from(*a))
+        .ok_or(WrongRequestDataError(ARGUMENTS_ERROR));
+
+    match command {
+        crate::SWITCH_SOCKET_COMMAND => {
+            let state = match arg(2)?.as_str() {
+                "true" | "t" => true,
+                "false" | "f" => false,
+                _ => return Err(WrongRequestDataError(ARGUMENTS_ERROR)),
+            };
+            Ok(Command::SwitchSocketCommand(arg(0)?, arg(1)?, state))
+        }
+        crate::GET_SOCKET_CONSUMED_POWER => Ok(Command::GetSocketConsumedPower(arg(0)?, arg(1)?)),
+        crate::GET_SOCKET_CONSUMED_ENERGY => Ok(Command::GetSocketConsumedEnergy(arg(0)?, arg(1)?)),
+        crate::RESET_SOCKET_ENERGY => Ok(Command::ResetSocketEnergy(arg(0)?, arg(1)?)),
+        // команды управления домом есть только во второй версии протокола
+        _ => Err(WrongRequestDataError(COMMAND_ERROR)),
+    }
+}
+
+/*
+    Обработка одного кадра первой версии: ответ всегда есть, в том числе для
+    кадра, который не удалось разобрать.
+ */
+pub fn handle_legacy_message(smart_house: &mut SmartHouse, bytes: &[u8]) -> String {
+    let response = match decode_legacy(bytes) {
+        Ok(command) => execute(smart_house, command),
+        Err(e) => Response::from(e),
+    };
+    response.to_legacy_string()
+}
+
+// ответ, после которого соединение переходит в режим доставки событий
+pub fn is_subscribed(message: &ServerMessage) -> bool {
+    matches!(message, ServerMessage::Response { response: Response::Subscribed, .. })
+}
+
+// кадры второй версии - JSON объекты, первая версия начинается с маркера S_M_C
+pub fn is_legacy_frame(bytes: &[u8]) -> bool {
+    bytes.first() != Some(&b'{')
+}
+
+pub fn execute(smart_house: &mut SmartHouse, command: Command) -> Response {
+    let revision = smart_house.get_revision();
+    let result = match command {
+        Command::SwitchSocketCommand(room, device, state) =>
+            smart_house.switch_socket(&room, &device, state).map(|_| Response::Ok),
+        Command::GetSocketConsumedPower(room, device) =>
+            smart_house.get_socket_state(&room, &device).map(Response::Power),
+        Command::GetSocketConsumedEnergy(room, device) =>
+            smart_house.get_socket_energy(&room, &device).map(Response::Energy),
+        Command::ResetSocketEnergy(room, device) =>
+            smart_house.reset_socket_energy(&room, &device).map(|_| Response::Ok),
+        Command::GetRooms =>
+            Ok(Response::Rooms(sorted(smart_house.get_rooms()))),
+        Command::AddRoom(room) =>
+            smart_house.add_room(&room).map(|_| Response::Ok),
+        Command::RemoveRoom(room) =>
+            smart_house.remove_room(&room).map(|_| Response::Ok),
+        Command::GetDevices(room) =>
+            smart_house.get_devices(&room)
+                .map(|devices| Response::Devices(sorted(devices)))
+                .ok_or(WrongRequestDataError(ROOM_ERROR)),
+        Command::AddDevice(room, kind, config) =>
+            smart_house.add_device(&room, &kind, config).map(|_| Response::Ok),
+        Command::RemoveDevice(room, device) =>
+            smart_house.remove_device(&room, &device).map(|_| Response::Ok),
+        Command::GetThermoData =>
+            smart_house.get_thermo_status().map(Response::TemperatureStatus),
+        Command::GetTemperature(room, device) =>
+            smart_house.get_temperature_status(&room, &device).map(Response::TemperatureStatus),
+        Command::GetRoomTemperature(room) =>
+            smart_house.get_room_temperature(&room).map(Response::Temperature),
+        Command::GetSensors => Ok(Response::Sensors(smart_house.get_sensors())),
+        Command::GetPollers => Ok(Response::Pollers(smart_house.get_poller_statuses().to_vec())),
+        Command::GetHistory(query) => smart_house.get_history(&query).map(Response::History),
+        Command::GetReport =>
+            Ok(Response::Report(smart_house.create_report())),
+        // слушателя событий добавляет сервер, которому принадлежит соединение
+        Command::Subscribe => Ok(Response::Subscribed),
+        Command::GetRules => Ok(Response::Rules(smart_house.get_rules().to_vec())),
+        Command::SetRuleEnabled(rule, enabled) =>
+            smart_house.set_rule_enabled(&rule, enabled).map(|_| Response::Ok),
+        Command::GetScenes => Ok(Response::Scenes(smart_house.get_scenes().to_vec())),
+        Command::AddScene(scene) =>
+            smart_house.add_scene(scene).map(|_| Response::Ok),
+        Command::ApplyScene(scene) =>
+            smart_house.apply_scene(&scene).map(|_| Response::Ok),
+        Command::GetGroups => Ok(Response::Groups(smart_house.get_groups().to_vec())),
+        Command::AddGroup(group) =>
+            smart_house.add_group(group).map(|_| Response::Ok),
+        Command::RemoveGroup(group) =>
+            smart_house.remove_group(&group).map(|_| Response::Ok),
+        Command::SwitchGroup(group, state) =>
+            smart_house.switch_group(&group, state).map(|results| members(results, |_| Response::Ok)),
+        Command::GetGroupPower(group) =>
+            smart_house.get_group_power(&group).map(|results| members(results, Response::Power)),
+        Command::GetJobs => smart_house.get_jobs().map(|jobs| Response::Jobs(jobs.to_vec())),
+        Command::AddJob(room, device, state, schedule) =>
+            smart_house.add_job(&room, &device, state, schedule).map(Response::JobAdded),
+        Command::RemoveJob(id) =>
+            smart_house.remove_job(id).map(|_| Response::Ok),
+    };
+    // если команда изменила дом, правила проверяются сразу; чтение их не запускает
+    if smart_house.get_revision() != revision {
+        smart_house.apply_rules();
+    }
+    result.unwrap_or_else(Response::from)
+}
+
+fn members<T>(results: Vec<MemberResult<T>>, to_response: impl Fn(T) -> Response) -> Response {
+    Response::Members(results.into_iter()
+        .map(|(room, device, result)| MemberResponse {
+            room,
+            device,
+            response: result.map(&to_response).unwrap_or_else(Response::from),
+        })
+        .collect())
+}
+
+// имена комнат и устройств хранятся в HashMap, отдаём их клиенту в стабильном порядке
+fn sorted(names: Vec<&str>) -> Vec<String> {
+    let mut names = names.into_iter().map(String::from).collect::<Vec<String>>();
+    names.sort();
+    names
+}
+
+// Обработка одного кадра второй версии: рукопожатия или запроса.
+pub fn handle_message(smart_house: &mut SmartHouse, bytes: &[u8]) -> ServerMessage {
+    match ClientMessage::decode(bytes) {
+        Ok(ClientMessage::Hello { versions }) => ServerMessage::Hello { version: negotiate(&versions) },
+        Ok(ClientMessage::Request { version, id, command }) => {
+            let response = if SUPPORTED_VERSIONS.contains(&version) {
+                execute(smart_house, command)
+            } else {
+                Response::error(ErrorCode::UnsupportedVersion, "unsupported protocol version")
+            };
+            ServerMessage::Response { version: PROTOCOL_VERSION, id, response }
+        }
+        // запрос не разобран, поэтому его id неизвестен
+        Err(e) => {
+            let response = Response::Error(ErrorCode::BadRequest, e.to_string());
+            ServerMessage::Response { version: PROTOCOL_VERSION, id: 0, response }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::Command;
+    use crate::device_registry::{DeviceConfig, SOCKET_KIND, THERMOMETER_KIND};
+    use std::time::{Duration, SystemTime};
+    use crate::errors::SmartHouseError;
+    use crate::codec::MAX_FRAME_SIZE;
+    use crate::history::{HistoryQuery, MAX_HISTORY_BUCKETS, SeriesKey};
+    use crate::protocol::*;
+    use crate::scheduler::{ManualClock, Schedule, Scheduler};
+    use crate::sensor::SensorHealth;
+    use crate::smart_house::SmartHouse;
+
+    #[test]
+    fn test_handshake_and_requests() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["living room"]);
+        smart_house.add_device("living room", SOCKET_KIND, DeviceConfig::new("Tv socket"))
+            .expect("error adding device");
+
+        assert_eq!(negotiate(&[1, 2, 3]), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(&[LEGACY_PROTOCOL_VERSION]), None);
+
+        let hello = ClientMessage::Hello { versions: vec![PROTOCOL_VERSION] }.encode().unwrap();
+        assert!(!is_legacy_frame(&hello));
+        assert_eq!(handle_message(&mut smart_house, &hello),
+                   ServerMessage::Hello { version: Some(PROTOCOL_VERSION) });
+
+        let command = Command::SwitchSocketCommand(
+            String::from("living room"), String::from("Tv socket"), true);
+        let request = ClientMessage::Request { version: PROTOCOL_VERSION, id: 17, command }.encode().unwrap();
+        let reply = handle_message(&mut smart_house, &request);
+        let reply = ServerMessage::decode(&reply.encode().unwrap()).unwrap();
+        assert_eq!(reply, ServerMessage::Response { version: PROTOCOL_VERSION, id: 17, response: Response::Ok });
+
+        let command = Command::GetSocketConsumedPower(String::from("kitchen"), String::from("Tv socket"));
+        let response = execute(&mut smart_house, command);
+        assert!(matches!(response, Response::Error(ErrorCode::NoSuchRoom, _)));
+        assert!(response.to_legacy_string().starts_with(crate::ERR_RESPONSE));
+
+        let command = Command::GetSocketConsumedPower(String::from("living room"), String::from("Tv socket"));
+        let request = ClientMessage::Request { version: 7, id: 18, command }.encode().unwrap();
+        let reply = handle_message(&mut smart_house, &request);
+        assert!(matches!(reply, ServerMessage::Response {
+            response: Response::Error(ErrorCode::UnsupportedVersion, _), .. }));
+
+        let reply = handle_message(&mut smart_house, b"{ not json");
+        assert!(matches!(reply, ServerMessage::Response {
+            response: Response::Error(ErrorCode::BadRequest, _), .. }));
+
+        let series = SeriesKey::Power { room: String::from("living room"), device: String::from("Tv socket") };
+        smart_house.record_power();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let response = execute(&mut smart_house, Command::GetHistory(HistoryQuery::last(series.clone(), day, day)));
+        assert!(matches!(&response, Response::History(buckets) if buckets.len() == 1));
+        assert!(response.to_legacy_string().ends_with("count 1"));
+        let now = SystemTime::now();
+        let reversed = HistoryQuery::new(series, now, now - day, Duration::ZERO);
+        assert!(matches!(execute(&mut smart_house, Command::GetHistory(reversed)),
+                         Response::Error(ErrorCode::BadRequest, _)));
+
+        // самые длинные значения в самом большом ответе всё равно помещаются в кадр
+        let bucket = Bucket { start: now, min: -f32::MAX, max: f32::MIN_POSITIVE, avg: -1.1754944e-38, count: u32::MAX };
+        let response = Response::History(vec![bucket; MAX_HISTORY_BUCKETS as usize]);
+        let message = ServerMessage::Response { version: PROTOCOL_VERSION, id: u64::MAX, response };
+        assert!(message.encode().unwrap().len() <= MAX_FRAME_SIZE);
+        let legacy = b"S_M_C\nG_S_C_P\nARGS\nroom1 Socket1\nE_M_C";
+        assert!(is_legacy_frame(legacy));
+        assert_eq!(decode_legacy(legacy).unwrap(),
+                   Command::GetSocketConsumedPower(String::from("room1"), String::from("Socket1")));
+    }
+
+    #[test]
+    fn test_malformed_legacy_requests() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+
+        assert_eq!(decode_legacy(b"S_M_C\nS_S_C\nARGS\nroom1 Socket1 t\nE_M_C").unwrap(),
+                   Command::SwitchSocketCommand(String::from("room1"), String::from("Socket1"), true));
+        assert_eq!(handle_legacy_message(&mut smart_house, b"S_M_C\nS_S_C\nARGS\nroom1 Socket1 true\nE_M_C"),
+                   crate::OK_RESPONSE);
+        assert_eq!(handle_legacy_message(&mut smart_house, b"S_M_C\nG_S_C_P\nARGS\nroom1 Socket1\nE_M_C"),
+                   "10");
+
+        let malformed : [&[u8]; 7] = [
+            b"",
+            b"\xff\xfe",
+            b"G_S_C_P\nARGS\nroom1 Socket1\nE_M_C",
+            b"S_M_C",
+            b"S_M_C\nG_S_C_P\nARGS\nroom1\nE_M_C",
+            b"S_M_C\nS_S_C\nARGS\nroom1 Socket1 maybe\nE_M_C",
+            b"S_M_C\nUNKNOWN\nARGS\n\nE_M_C",
+        ];
+        for bytes in malformed {
+            assert!(matches!(decode_legacy(bytes), Err(SmartHouseError::WrongRequestDataError(_))));
+            assert!(handle_legacy_message(&mut smart_house, bytes).starts_with(crate::ERR_RESPONSE));
+        }
+    }
+
+    #[test]
+    fn test_house_management_commands() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["living room"]);
+
+        assert_eq!(execute(&mut smart_house, Command::AddRoom(String::from("kitchen"))), Response::Ok);
+        assert_eq!(execute(&mut smart_house, Command::GetRooms),
+                   Response::Rooms(vec![String::from("kitchen"), String::from("living room")]));
+
+        let mut config = DeviceConfig::new("Kettle");
+        config.power = Some(2000.0);
+        let command = Command::AddDevice(String::from("kitchen"), String::from(SOCKET_KIND), config);
+        assert_eq!(execute(&mut smart_house, command), Response::Ok);
+        let command = Command::AddDevice(String::from("kitchen"), String::from(THERMOMETER_KIND),
+                                         DeviceConfig::new("Thermo"));
+        assert_eq!(execute(&mut smart_house, command), Response::Ok);
+        let command = Command::AddDevice(String::from("kitchen"), String::from("toaster"),
+                                         DeviceConfig::new("Toaster"));
+        assert!(matches!(execute(&mut smart_house, command), Response::Error(ErrorCode::UnknownDeviceKind, _)));
+        assert_eq!(execute(&mut smart_house, Command::GetDevices(String::from("kitchen"))),
+                   Response::Devices(vec![String::from("Kettle"), String::from("Thermo")]));
+        assert!(matches!(execute(&mut smart_house, Command::GetDevices(String::from("hall"))),
+                         Response::Error(ErrorCode::NoSuchRoom, _)));
+
+        // до первого показания температуры нет, а не 0 °C
+        assert!(matches!(execute(&mut smart_house, Command::GetThermoData), Response::Error(ErrorCode::NoData, _)));
+        smart_house.set_thermo_data(21.5);
+        let Response::TemperatureStatus(status) = execute(&mut smart_house, Command::GetThermoData)
+            else { panic!("temperature expected") };
+        assert_eq!((status.temperature, status.health), (21.5, SensorHealth::Online));
+        assert!(status.last_seen.is_some());
+        let command = Command::GetTemperature(String::from("kitchen"), String::from("Thermo"));
+        assert!(matches!(execute(&mut smart_house, command), Response::TemperatureStatus(s) if s.temperature == 21.5));
+        let command = Command::GetTemperature(String::from("kitchen"), String::from("Kettle"));
+        assert!(matches!(execute(&mut smart_house, command), Response::Error(ErrorCode::Unsupported, _)));
+
+        // отчёт проходит через JSON без потерь
+        let response = execute(&mut smart_house, Command::GetReport);
+        let message = ServerMessage::Response { version: PROTOCOL_VERSION, id: 1, response };
+        let Ok(ServerMessage::Response { response: Response::Report(report), .. })
+            = ServerMessage::decode(&message.encode().unwrap()) else { panic!("report expected") };
+        assert_eq!(report.rooms.len(), 2);
+        assert_eq!(report.rooms[0].devices.len(), 2);
+        assert_eq!(report.remote_temperature, 21.5);
+
+        assert_eq!(execute(&mut smart_house, Command::GetRules), Response::Rules(vec![]));
+        assert!(matches!(execute(&mut smart_house, Command::SetRuleEnabled(String::from("rule"), false)),
+                         Response::Error(ErrorCode::NoSuchRule, _)));
+
+        let scene = Scene::new("Away").with_state("kitchen", "Kettle", false);
+        assert_eq!(execute(&mut smart_house, Command::AddScene(scene.clone())), Response::Ok);
+        assert_eq!(execute(&mut smart_house, Command::GetScenes), Response::Scenes(vec![scene]));
+        assert_eq!(execute(&mut smart_house, Command::ApplyScene(String::from("Away"))), Response::Ok);
+        assert!(matches!(execute(&mut smart_house, Command::ApplyScene(String::from("Night"))),
+                         Response::Error(ErrorCode::NoSuchScene, _)));
+
+        let group = DeviceGroup::new("kitchen").with_member("kitchen", "Kettle").with_member("kitchen", "Thermo");
+        assert_eq!(execute(&mut smart_house, Command::AddGroup(group)), Response::Ok);
+        let Response::Members(members) = execute(&mut smart_house, Command::SwitchGroup(String::from("kitchen"), true))
+            else { panic!("member responses expected") };
+        assert_eq!(members[0].device, "Kettle");
+        assert_eq!(members[0].response, Response::Ok);
+        assert!(matches!(members[1].response, Response::Error(ErrorCode::Unsupported, _)));
+        assert!(matches!(execute(&mut smart_house, Command::GetGroupPower(String::from("heaters"))),
+                         Response::Error(ErrorCode::NoSuchGroup, _)));
+
+        assert!(matches!(execute(&mut smart_house, Command::AddRoom(String::from("kitchen"))),
+                         Response::Error(ErrorCode::BadRequest, _)));
+        assert_eq!(execute(&mut smart_house, Command::GetDevices(String::from("kitchen"))),
+                   Response::Devices(vec![String::from("Kettle"), String::from("Thermo")]));
+        let command = Command::RemoveDevice(String::from("kitchen"), String::from("Kettle"));
+        assert_eq!(execute(&mut smart_house, command.clone()), Response::Ok);
+        assert!(matches!(execute(&mut smart_house, command), Response::Error(ErrorCode::NoSuchDevice, _)));
+        assert_eq!(execute(&mut smart_house, Command::RemoveRoom(String::from("kitchen"))), Response::Ok);
+        assert!(matches!(execute(&mut smart_house, Command::RemoveRoom(String::from("kitchen"))),
+                         Response::Error(ErrorCode::NoSuchRoom, _)));
+        assert_eq!(execute(&mut smart_house, Command::GetRooms),
+                   Response::Rooms(vec![String::from("living room")]));
+    }
+
+    #[test]
+    fn test_job_commands() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket_Boiler"))
+            .expect("error adding device");
+        assert!(matches!(execute(&mut smart_house, Command::GetJobs), Response::Error(ErrorCode::Unsupported, _)));
+
+        let monday = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let clock = ManualClock::new(monday.and_hms_opt(5, 0, 0).unwrap());
+        smart_house.set_scheduler(Scheduler::new(Box::new(clock.clone())));
+        let six = Schedule::Cron(String::from("0 6 * * 1-5"));
+        let command = Command::AddJob(String::from("room1"), String::from("Socket_Boiler"), true, six.clone());
+        assert_eq!(execute(&mut smart_house, command), Response::JobAdded(1));
+        let command = Command::AddJob(String::from("room1"), String::from("Kettle"), true, six);
+        assert!(matches!(execute(&mut smart_house, command), Response::Error(ErrorCode::NoSuchDevice, _)));
+        let wrong = Schedule::Cron(String::from("0 25 * * *"));
+        let command = Command::AddJob(String::from("room1"), String::from("Socket_Boiler"), true, wrong);
+        assert!(matches!(execute(&mut smart_house, command), Response::Error(ErrorCode::BadRequest, _)));
+        let Response::Jobs(jobs) = execute(&mut smart_house, Command::GetJobs) else { panic!("jobs expected") };
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].next_run, Some(monday.and_hms_opt(6, 0, 0).unwrap()));
+
+        // задание, добавленное по сети, выполняется планировщиком дома
+        clock.set(monday.and_hms_opt(6, 0, 0).unwrap());
+        assert_eq!(smart_house.run_scheduled_jobs(), vec![1]);
+        assert!(smart_house.is_switched_on("room1", "Socket_Boiler").unwrap());
+
+        assert_eq!(execute(&mut smart_house, Command::RemoveJob(1)), Response::Ok);
+        assert!(matches!(execute(&mut smart_house, Command::RemoveJob(1)), Response::Error(ErrorCode::NoSuchJob, _)));
+        assert_eq!(execute(&mut smart_house, Command::GetJobs), Response::Jobs(vec![]));
+    }
+}
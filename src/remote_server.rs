@@ -1,39 +1,472 @@
+use std::f64::consts::PI;
+use std::fs;
 use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
 use std::thread;
-use std::time::Duration;
-use rand::Rng;
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use crate::errors::SmartHouseError;
+use crate::errors::SmartHouseError::{NetworkError, SerializationError, StorageError, WrongRequestDataError};
+use crate::sensor::SensorReading;
+
+const DEFAULT_SENSOR_ID : u32 = 1;
+const DEFAULT_OWN_ADDR : &str = "127.0.0.1:8082";
+const DEFAULT_TARGET_ADDR : &str = "127.0.0.1:8083";
+const DEFAULT_INTERVAL_MS : u64 = 3000;
+const DEFAULT_MIN_TEMPERATURE : f32 = 23.0;
+const DEFAULT_MAX_TEMPERATURE : f32 = 28.0;
+const REPLAY_ERROR : &str = "replay file has no temperatures";
+const PROFILE_ERROR : &str = "invalid value profile";
+const FAULTS_ERROR : &str = "fault probabilities must be between 0 and 1";
+const SENSOR_IDS_ERROR : &str = "sensor ids do not fit into u32";
+const INTERVAL_ERROR : &str = "send interval must be positive";
+const TARGETS_ERROR : &str = "no target addresses";
+
+// Как меняется температура датчика со временем, прошедшим с запуска симулятора.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Profile {
+    // равномерно случайное значение в [min, max)
+    Uniform { min : f32, max : f32 },
+    // суточная кривая: минимум (ночь) в момент `phase_secs`, максимум (день) через полпериода
+    Sine {
+        mean : f32,
+        amplitude : f32,
+        #[serde(default = "default_period_secs")]
+        period_secs : f64,
+        #[serde(default)]
+        phase_secs : f64,
+    },
+    // скачок с `from` на `to` через `after_secs`
+    Step { from : f32, to : f32, after_secs : f64 },
+    // другой профиль плюс равномерный шум в [-amplitude, amplitude]
+    Noise { base : Box<Profile>, amplitude : f32 },
+    /*
+        Значения из CSV файла по одному на отправку, по кругу. Температура - последнее поле
+        строки, так что подходят и "23.5", и "2024-01-01T06:00,23.5". Строки, где её нет
+        (заголовок, пустые, комментарии с `#`), пропускаются.
+     */
+    Replay {
+        path : PathBuf,
+        #[serde(skip)]
+        values : Vec<f32>,
+    },
+}
+
+fn default_period_secs() -> f64 {
+    24.0 * 60.0 * 60.0
+}
+
+/*
+    Неисправности сети: вероятность от 0 до 1 для каждой датаграммы.
+    Переставленная датаграмма уходит после следующей, испорченная обрезается так,
+    что получатель не сможет её разобрать. В датаграмме с перевёрнутыми байтами длина
+    остаётся верной, а значения полей - нет: её получатель может отсеять только по содержимому.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Faults {
+    #[serde(default)]
+    pub drop : f64,
+    #[serde(default)]
+    pub duplicate : f64,
+    #[serde(default)]
+    pub reorder : f64,
+    #[serde(default)]
+    pub corrupt : f64,
+    #[serde(default)]
+    pub flip : f64,
+}
+
+/*
+    Настройки симулятора удалённых датчиков: `sensors` датчиков с id начиная с `first_sensor_id`
+    раз в `interval_ms` отправляют показания на каждый адрес из `targets`.
+    `seed` делает значения и неисправности воспроизводимыми.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulatorConfig {
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr : String,
+    #[serde(default = "default_targets")]
+    pub targets : Vec<String>,
+    #[serde(default = "default_sensors")]
+    pub sensors : u32,
+    #[serde(default = "default_first_sensor_id")]
+    pub first_sensor_id : u32,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms : u64,
+    #[serde(default = "default_profile")]
+    pub profile : Profile,
+    #[serde(default)]
+    pub faults : Faults,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed : Option<u64>,
+}
+
+fn default_bind_addr() -> String {
+    String::from(DEFAULT_OWN_ADDR)
+}
+
+fn default_targets() -> Vec<String> {
+    vec![String::from(DEFAULT_TARGET_ADDR)]
+}
+
+fn default_sensors() -> u32 {
+    1
+}
+
+fn default_first_sensor_id() -> u32 {
+    DEFAULT_SENSOR_ID
+}
+
+fn default_interval_ms() -> u64 {
+    DEFAULT_INTERVAL_MS
+}
+
+fn default_profile() -> Profile {
+    Profile::Uniform { min: DEFAULT_MIN_TEMPERATURE, max: DEFAULT_MAX_TEMPERATURE }
+}
+
+impl SimulatorConfig {
+    pub fn read(path: &Path) -> Result<Self, SmartHouseError> {
+        let data = fs::read_to_string(path).map_err(StorageError)?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Self, SmartHouseError> {
+        serde_json::from_str(data).map_err(SerializationError)
+    }
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            bind_addr: default_bind_addr(),
+            targets: default_targets(),
+            sensors: default_sensors(),
+            first_sensor_id: DEFAULT_SENSOR_ID,
+            interval_ms: DEFAULT_INTERVAL_MS,
+            profile: default_profile(),
+            faults: Faults::default(),
+            seed: None,
+        }
+    }
+}
+
+impl Faults {
+    pub fn validate(&self) -> Result<(), SmartHouseError> {
+        let probabilities = [self.drop, self.duplicate, self.reorder, self.corrupt, self.flip];
+        if !probabilities.iter().all(|p| (0.0..=1.0).contains(p)) {
+            return Err(WrongRequestDataError(FAULTS_ERROR));
+        }
+        Ok(())
+    }
+}
+
+impl Profile {
+    // проверяет параметры, с которыми `value` не смог бы посчитать значение
+    pub fn validate(&self) -> Result<(), SmartHouseError> {
+        let valid = match self {
+            Profile::Uniform { min, max } => min < max && (max - min).is_finite(),
+            Profile::Sine { mean, amplitude, period_secs, phase_secs } =>
+                mean.is_finite() && amplitude.is_finite() && *period_secs > 0.0 && phase_secs.is_finite(),
+            Profile::Step { from, to, .. } => from.is_finite() && to.is_finite(),
+            Profile::Noise { base, amplitude } => {
+                base.validate()?;
+                *amplitude >= 0.0 && amplitude.is_finite()
+            }
+            Profile::Replay { .. } => true,
+        };
+        if !valid {
+            return Err(WrongRequestDataError(PROFILE_ERROR));
+        }
+        Ok(())
+    }
+
+    // загружает файлы, на которые ссылается профиль
+    pub fn load(&mut self) -> Result<(), SmartHouseError> {
+        match self {
+            Profile::Noise { base, .. } => base.load(),
+            Profile::Replay { path, values } => {
+                let data = fs::read_to_string(path).map_err(StorageError)?;
+                *values = data.lines()
+                    .filter(|line| !line.trim_start().starts_with('#'))
+                    .filter_map(|line| line.rsplit(',').next()?.trim().parse::<f32>().ok())
+                    .collect();
+                if values.is_empty() {
+                    return Err(WrongRequestDataError(REPLAY_ERROR));
+                }
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+
+    // значение для отправки номер `tick`, сделанной через `elapsed` после запуска
+    pub fn value(&self, tick: u64, elapsed: Duration, rng: &mut impl Rng) -> f32 {
+        let seconds = elapsed.as_secs_f64();
+        match self {
+            Profile::Uniform { min, max } => rng.gen_range(*min..*max),
+            Profile::Sine { mean, amplitude, period_secs, phase_secs } => {
+                let angle = 2.0 * PI * (seconds - phase_secs) / period_secs;
+                mean - amplitude * angle.cos() as f32
+            }
+            Profile::Step { from, to, after_secs } => if seconds < *after_secs { *from } else { *to },
+            Profile::Noise { base, amplitude } =>
+                base.value(tick, elapsed, rng) + rng.gen_range(-*amplitude..=*amplitude),
+            Profile::Replay { values, .. } => values[(tick % values.len() as u64) as usize],
+        }
+    }
+}
+
+/*
+    Состояние симулятора без сокета: какие датаграммы отправить в очередной раз.
+    Номера показаний у каждого датчика свои и растут подряд, в том числе для потерянных.
+ */
+pub struct Simulator {
+    config : SimulatorConfig,
+    rng : StdRng,
+    started : Instant,
+    tick : u64,
+    // переставленная датаграмма ждёт следующей
+    held : Option<Vec<u8>>,
+}
+
+impl Simulator {
+    // настройки проверяются сразу, чтобы ошибка в них не уронила поток отправки на первом же шаге
+    pub fn new(mut config: SimulatorConfig) -> Result<Self, SmartHouseError> {
+        config.faults.validate()?;
+        config.profile.validate()?;
+        if config.first_sensor_id.checked_add(config.sensors.saturating_sub(1)).is_none() {
+            return Err(WrongRequestDataError(SENSOR_IDS_ERROR));
+        }
+        // с нулевым интервалом поток отправки крутился бы без остановки
+        if config.interval_ms == 0 {
+            return Err(WrongRequestDataError(INTERVAL_ERROR));
+        }
+        if config.targets.is_empty() {
+            return Err(WrongRequestDataError(TARGETS_ERROR));
+        }
+        config.profile.load()?;
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Ok(Simulator { config, rng, started: Instant::now(), tick: 0, held: None })
+    }
+
+    pub fn next_datagrams(&mut self) -> Vec<Vec<u8>> {
+        let elapsed = self.started.elapsed();
+        let mut datagrams = Vec::new();
+        for sensor in 0..self.config.sensors {
+            let temperature = self.config.profile.value(self.tick, elapsed, &mut self.rng);
+            let reading = SensorReading::new(self.config.first_sensor_id + sensor, self.tick as u32, temperature);
+            self.apply_faults(reading.encode().to_vec(), &mut datagrams);
+        }
+        self.tick += 1;
+        datagrams
+    }
+
+    fn apply_faults(&mut self, mut datagram: Vec<u8>, datagrams: &mut Vec<Vec<u8>>) {
+        let faults = &self.config.faults;
+        let rng = &mut self.rng;
+        if rng.gen_bool(faults.drop) {
+            return;
+        }
+        if rng.gen_bool(faults.flip) {
+            flip_bytes(&mut datagram, rng);
+        }
+        if rng.gen_bool(faults.corrupt) {
+            // ни 4, ни 20 байт: такую длину получатель не разбирает
+            datagram.truncate(rng.gen_range(5..datagram.len()));
+        }
+        let copies = if rng.gen_bool(faults.duplicate) { 2 } else { 1 };
+        if self.held.is_none() && rng.gen_bool(faults.reorder) {
+            self.held = Some(datagram);
+            return;
+        }
+        for _ in 0..copies {
+            datagrams.push(datagram.clone());
+        }
+        datagrams.extend(self.held.take());
+    }
+}
+
+// меняет от одного до трёх разных байтов датаграммы, не меняя её длину
+fn flip_bytes(datagram: &mut [u8], rng: &mut impl Rng) {
+    let count = rng.gen_range(1..=3).min(datagram.len());
+    for i in rand::seq::index::sample(rng, datagram.len(), count) {
+        datagram[i] ^= rng.gen_range(1..=u8::MAX);
+    }
+}
 
 pub struct RemoteServer {}
 
 impl RemoteServer {
 
     pub fn start() {
+        Self::start_with_config(SimulatorConfig::default());
+    }
 
-        thread::spawn(|| {
-            let socket = UdpSocket::bind("127.0.0.1:8082").expect("can't bind socket");
-
-            loop {
-                thread::sleep(Duration::from_secs(3));
-                let data = Self::generate_temperature_data();
-                println!("data : {data}");
-                let buf: &mut [u8; 4] = &mut Default::default();
-                let bites = data.to_be_bytes();
-                for (i,e) in bites.iter().enumerate() {
-                    buf[i] = *e;
-                }
-                socket.connect("127.0.0.1:8083").expect("could not connect to 127.0.0.1:8083");
-                let res = socket.send(buf);
-                if res.is_err() {
-                    println!("error : {}", res.err().unwrap());
-                }
+    // датчик с заданным id раз в 3 секунды отправляет показание с очередным номером
+    pub fn start_sensor(sensor_id: u32, own_addr: &str, target_addr: &str) {
+        let config = SimulatorConfig {
+            bind_addr: String::from(own_addr),
+            targets: vec![String::from(target_addr)],
+            first_sensor_id: sensor_id,
+            ..SimulatorConfig::default()
+        };
+        Self::start_with_config(config);
+    }
+
+    pub fn start_with_config(config: SimulatorConfig) {
+        thread::spawn(move || {
+            if let Err(e) = Self::run(config) {
+                println!("sensor simulator stopped: {e}");
             }
         });
     }
 
-    fn generate_temperature_data() -> f32 {
-        rand::thread_rng().gen_range(23f32..28f32)
+    // работает, пока не сломается сокет; ошибка отправки на один адрес только печатается
+    pub fn run(config: SimulatorConfig) -> Result<(), SmartHouseError> {
+        let socket = UdpSocket::bind(&config.bind_addr).map_err(NetworkError)?;
+        let interval = Duration::from_millis(config.interval_ms);
+        let targets = config.targets.clone();
+        let mut simulator = Simulator::new(config)?;
+        loop {
+            thread::sleep(interval);
+            for datagram in simulator.next_datagrams() {
+                match SensorReading::decode(&datagram) {
+                    Ok(reading) => println!("sensor {} data : {}", reading.sensor_id, reading.temperature),
+                    Err(_) => println!("sending corrupt datagram of {} bytes", datagram.len()),
+                }
+                for target in &targets {
+                    if let Err(e) = socket.send_to(&datagram, target) {
+                        println!("error : {e}");
+                    }
+                }
+            }
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::Duration;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::errors::SmartHouseError;
+    use crate::remote_server::{Faults, flip_bytes, Profile, Simulator, SimulatorConfig};
+    use crate::sensor::SensorReading;
+
+    fn simulator(sensors: u32, faults: Faults) -> Simulator {
+        let config = SimulatorConfig {
+            sensors,
+            profile: Profile::Step { from: 20.0, to: 25.0, after_secs: 3600.0 },
+            faults,
+            seed: Some(7),
+            ..SimulatorConfig::default()
+        };
+        Simulator::new(config).unwrap()
+    }
+
+    fn readings(datagrams: Vec<Vec<u8>>) -> Vec<(u32, u32)> {
+        datagrams.iter()
+            .map(|d| SensorReading::decode(d).unwrap())
+            .map(|r| (r.sensor_id, r.seq))
+            .collect()
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        let day = Profile::Sine { mean: 20.0, amplitude: 5.0, period_secs: 86400.0, phase_secs: 0.0 };
+        assert_eq!(day.value(0, hours(0), &mut rng), 15.0);
+        assert_eq!(day.value(0, hours(12), &mut rng), 25.0);
+        assert!((day.value(0, hours(6), &mut rng) - 20.0).abs() < 0.001);
+
+        let step = Profile::Step { from: 20.0, to: 25.0, after_secs: 60.0 };
+        assert_eq!(step.value(0, Duration::from_secs(59), &mut rng), 20.0);
+        assert_eq!(step.value(0, Duration::from_secs(60), &mut rng), 25.0);
+        let noisy = Profile::Noise { base: Box::new(step), amplitude: 0.5 };
+        assert!((0..100).map(|_| noisy.value(0, hours(0), &mut rng)).all(|t| (19.5..=20.5).contains(&t)));
+
+        let path = env::temp_dir().join(format!("smart_house_replay_{}.csv", std::process::id()));
+        std::fs::write(&path, "time,temperature\n# night\n00:00,18.5\n\n01:00,19\n").unwrap();
+        let mut replay = Profile::Replay { path: path.clone(), values: vec![] };
+        replay.load().unwrap();
+        let values = (0..3).map(|tick| replay.value(tick, hours(0), &mut rng)).collect::<Vec<f32>>();
+        assert_eq!(values, vec![18.5, 19.0, 18.5]);
+        std::fs::write(&path, "time,temperature\n").unwrap();
+        assert!(replay.load().is_err());
+        std::fs::remove_file(&path).unwrap();
 
+        let config = SimulatorConfig::parse(r#"{ "sensors": 3, "profile": { "Sine": { "mean": 21.0, "amplitude": 4.0 } } }"#).unwrap();
+        assert_eq!(config.targets, vec!["127.0.0.1:8083"]);
+        assert!(matches!(config.profile, Profile::Sine { period_secs, .. } if period_secs == 86400.0));
+    }
+
+    #[test]
+    fn test_bad_configs() {
+        let uniform = |min, max| Profile::Uniform { min, max };
+        let noise = |amplitude| Profile::Noise { base: Box::new(uniform(20.0, 25.0)), amplitude };
+        let sine = Profile::Sine { mean: 20.0, amplitude: 5.0, period_secs: 0.0, phase_secs: 0.0 };
+        for profile in [uniform(25.0, 25.0), uniform(25.0, 20.0), uniform(f32::MIN, f32::MAX), noise(-1.0),
+                        Profile::Noise { base: Box::new(uniform(25.0, 20.0)), amplitude: 1.0 }, sine] {
+            let config = SimulatorConfig { profile, ..SimulatorConfig::default() };
+            assert!(matches!(Simulator::new(config), Err(SmartHouseError::WrongRequestDataError(_))));
+        }
+        for faults in [Faults { drop: 1.5, ..Faults::default() }, Faults { duplicate: -0.1, ..Faults::default() },
+                       Faults { reorder: f64::NAN, ..Faults::default() }, Faults { corrupt: 2.0, ..Faults::default() }] {
+            let config = SimulatorConfig { faults, ..SimulatorConfig::default() };
+            assert!(matches!(Simulator::new(config), Err(SmartHouseError::WrongRequestDataError(_))));
+        }
+        let config = SimulatorConfig { sensors: 2, first_sensor_id: u32::MAX, ..SimulatorConfig::default() };
+        assert!(Simulator::new(config).is_err());
+        let config = SimulatorConfig { sensors: 1, first_sensor_id: u32::MAX, ..SimulatorConfig::default() };
+        assert!(Simulator::new(config).is_ok());
+        let config = SimulatorConfig { interval_ms: 0, ..SimulatorConfig::default() };
+        assert!(matches!(Simulator::new(config), Err(SmartHouseError::WrongRequestDataError(_))));
+        let config = SimulatorConfig { targets: vec![], ..SimulatorConfig::default() };
+        assert!(matches!(Simulator::new(config), Err(SmartHouseError::WrongRequestDataError(_))));
+        assert!(Simulator::new(SimulatorConfig { profile: noise(0.0), ..SimulatorConfig::default() }).is_ok());
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut healthy = simulator(2, Faults::default());
+        assert_eq!(readings(healthy.next_datagrams()), vec![(1, 0), (2, 0)]);
+        assert_eq!(readings(healthy.next_datagrams()), vec![(1, 1), (2, 1)]);
+
+        let mut dropping = simulator(2, Faults { drop: 1.0, ..Faults::default() });
+        assert!(dropping.next_datagrams().is_empty());
 
+        let mut duplicating = simulator(1, Faults { duplicate: 1.0, ..Faults::default() });
+        assert_eq!(readings(duplicating.next_datagrams()), vec![(1, 0), (1, 0)]);
+
+        // каждая вторая датаграмма задерживается до следующей
+        let mut reordering = simulator(1, Faults { reorder: 1.0, ..Faults::default() });
+        let sent = (0..4).flat_map(|_| reordering.next_datagrams()).collect::<Vec<Vec<u8>>>();
+        assert_eq!(readings(sent), vec![(1, 1), (1, 0), (1, 3), (1, 2)]);
+
+        let mut corrupting = simulator(3, Faults { corrupt: 1.0, ..Faults::default() });
+        let sent = corrupting.next_datagrams();
+        assert_eq!(sent.len(), 3);
+        assert!(sent.iter().all(|d| SensorReading::decode(d).is_err()));
+
+        // перевёрнутые байты: датаграмма разбирается, но поля в ней уже другие
+        let mut flipping = simulator(3, Faults { flip: 1.0, ..Faults::default() });
+        let sent = flipping.next_datagrams();
+        assert_eq!(sent.len(), 3);
+        assert!(sent.iter().all(|d| SensorReading::decode(d).is_ok()));
+        let mut rng = StdRng::seed_from_u64(1);
+        let reading = SensorReading::new(1, 0, 20.0).encode();
+        for _ in 0..100 {
+            let mut flipped = reading;
+            flip_bytes(&mut flipped, &mut rng);
+            assert_ne!(flipped, reading);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt::{Display, Formatter};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use crate::sensor::SensorHealth;
+
+// Состояние одного устройства на момент составления отчёта.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceReport {
+    pub name : String,
+    pub is_on : Option<bool>,
+    pub consumed_power : Option<f32>,
+    pub consumed_energy : Option<f64>,
+    pub temperature : Option<f32>,
+    // для термометров удалённых датчиков: когда пришло показание и насколько оно свежее
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen : Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health : Option<SensorHealth>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomReport {
+    pub name : String,
+    pub devices : Vec<DeviceReport>,
+    pub total_power : f32,
+    pub total_energy : f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HouseReport {
+    pub name : String,
+    pub rooms : Vec<RoomReport>,
+    pub total_power : f32,
+    pub total_energy : f64,
+    pub remote_temperature : f32,
+    // состояние общего значения удалённых датчиков; `None`, если оно ни разу не приходило
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_last_seen : Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_health : Option<SensorHealth>,
+}
+
+impl RoomReport {
+    pub fn new(name : &str, mut devices : Vec<DeviceReport>) -> Self {
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        let total_power = devices.iter()
+            .filter_map(|d| d.consumed_power)
+            .sum();
+        let total_energy = devices.iter()
+            .filter_map(|d| d.consumed_energy)
+            .sum();
+        RoomReport { name: String::from(name), devices, total_power, total_energy }
+    }
+}
+
+impl HouseReport {
+    pub fn new(name : &str, mut rooms : Vec<RoomReport>, remote_temperature : f32) -> Self {
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        let total_power = rooms.iter()
+            .map(|r| r.total_power)
+            .sum();
+        let total_energy = rooms.iter()
+            .map(|r| r.total_energy)
+            .sum();
+        HouseReport {
+            name: String::from(name),
+            rooms,
+            total_power,
+            total_energy,
+            remote_temperature,
+            remote_last_seen: None,
+            remote_health: None,
+        }
+    }
+}
+
+fn format_last_seen(last_seen: SystemTime, health: Option<SensorHealth>) -> String {
+    let seconds = last_seen.elapsed().unwrap_or_default().as_secs();
+    match health {
+        Some(health) => format!("{health}, last seen {seconds}s ago"),
+        None => format!("last seen {seconds}s ago")
+    }
+}
+
+impl Display for DeviceReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut state = Vec::new();
+        if let Some(is_on) = self.is_on {
+            state.push(String::from(if is_on { "on" } else { "off" }));
+        }
+        if let Some(power) = self.consumed_power {
+            state.push(format!("power {power:.2}"));
+        }
+        if let Some(energy) = self.consumed_energy {
+            state.push(format!("energy {energy:.3} Wh"));
+        }
+        if let Some(temperature) = self.temperature {
+            state.push(format!("temperature {temperature:.1}"));
+        }
+        if let Some(last_seen) = self.last_seen {
+            state.push(format_last_seen(last_seen, self.health));
+        }
+        write!(f, "{}: {}", self.name, state.join(", "))
+    }
+}
+
+impl Display for RoomReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "room {}: total power {:.2}, total energy {:.3} Wh",
+                 self.name, self.total_power, self.total_energy)?;
+        for device in &self.devices {
+            writeln!(f, "  {device}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for HouseReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "house {}: total power {:.2}, total energy {:.3} Wh, remote temperature {:.1}",
+               self.name, self.total_power, self.total_energy, self.remote_temperature)?;
+        match self.remote_last_seen {
+            Some(last_seen) => writeln!(f, " ({})", format_last_seen(last_seen, self.remote_health))?,
+            None => writeln!(f)?
+        }
+        for room in &self.rooms {
+            write!(f, "{room}")?;
+        }
+        Ok(())
+    }
+}
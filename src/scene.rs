@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/*
+    Сцена - именованный набор состояний устройств, например "Ночь" или "Никого нет дома":
+    для каждой комнаты - какие устройства должны быть включены, а какие выключены.
+    Устройства, не упомянутые в сцене, она не трогает.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub name : String,
+    // комната -> устройство -> включено ли оно
+    #[serde(default)]
+    pub states : BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+impl Scene {
+    pub fn new(name: &str) -> Self {
+        Scene { name: String::from(name), states: BTreeMap::new() }
+    }
+
+    pub fn with_state(mut self, room: &str, device: &str, state: bool) -> Self {
+        self.states.entry(String::from(room))
+            .or_default()
+            .insert(String::from(device), state);
+        self
+    }
+
+    // пары (комната, устройство) с нужным состоянием в стабильном порядке
+    pub fn targets(&self) -> Vec<(&str, &str, bool)> {
+        self.states.iter()
+            .flat_map(|(room, devices)| devices.iter()
+                .map(move |(device, state)| (room.as_str(), device.as_str(), *state)))
+            .collect()
+    }
+}
@@ -0,0 +1,408 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use chrono::{Datelike, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use crate::errors::{JOB_ERROR, SCHEDULE_ERROR, SmartHouseError};
+use crate::errors::SmartHouseError::{SerializationError, StorageError, WrongRequestDataError};
+use crate::smart_house::SmartHouse;
+
+// как часто серверы проверяют, не пора ли выполнить задания
+pub const SCHEDULER_TICK : Duration = Duration::from_secs(1);
+// насколько далеко вперёд ищется следующий запуск cron-выражения (29 февраля бывает раз в 4 года)
+const MAX_CRON_DAYS : u64 = 366 * 4 + 1;
+
+/*
+    Источник текущего времени для планировщика. Время местное, без часового пояса:
+    "в 06:00" означает 06:00 по часам дома. В тестах вместо системных часов
+    подставляются ручные.
+ */
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+// Часы, которые идут только когда их переводят.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now : Arc<Mutex<NaiveDateTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: NaiveDateTime) -> Self {
+        ManualClock { now: Arc::new(Mutex::new(now)) }
+    }
+
+    pub fn set(&self, now: NaiveDateTime) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+    Once(NaiveDateTime),
+    Daily(NaiveTime),
+    Weekly { days : Vec<Weekday>, time : NaiveTime },
+    // "минуты часы день_месяца месяц день_недели", например "0 6 * * 1-5" - в 06:00 по будням
+    Cron(String),
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<(), SmartHouseError> {
+        match self {
+            Schedule::Weekly { days, .. } if days.is_empty() => Err(WrongRequestDataError(SCHEDULE_ERROR)),
+            Schedule::Cron(expression) => CronExpression::parse(expression).map(|_| ()),
+            _ => Ok(())
+        }
+    }
+
+    // ближайший запуск строго после `after`; `None`, если запусков больше не будет
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Schedule::Once(at) => (*at > after).then_some(*at),
+            Schedule::Daily(time) => (0..=1)
+                .map(|day| after.date() + Days::new(day))
+                .map(|date| date.and_time(*time))
+                .find(|at| *at > after),
+            Schedule::Weekly { days, time } => (0..=7)
+                .map(|day| after.date() + Days::new(day))
+                .filter(|date| days.contains(&date.weekday()))
+                .map(|date| date.and_time(*time))
+                .find(|at| *at > after),
+            Schedule::Cron(expression) => CronExpression::parse(expression).ok()?.next_after(after),
+        }
+    }
+}
+
+/*
+    Разобранное cron-выражение: для каждого поля - какие значения подходят.
+    Поддерживаются `*`, числа, диапазоны `1-5`, списки через запятую и шаг через `/` (`0-59/15`).
+    Как в cron, если ограничены и день месяца, и день недели, достаточно совпадения любого из них.
+ */
+struct CronExpression {
+    minutes : Vec<bool>,
+    hours : Vec<bool>,
+    days : Vec<bool>,
+    months : Vec<bool>,
+    // 0 - воскресенье
+    weekdays : Vec<bool>,
+    any_day : bool,
+    any_weekday : bool,
+}
+
+impl CronExpression {
+    fn parse(expression: &str) -> Result<Self, SmartHouseError> {
+        let fields = expression.split_whitespace().collect::<Vec<&str>>();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(WrongRequestDataError(SCHEDULE_ERROR));
+        };
+        // 7 - тоже воскресенье
+        let mut weekdays_allowed = Self::parse_field(weekdays, 0, 7)?;
+        weekdays_allowed[0] |= weekdays_allowed.pop().unwrap_or(false);
+        Ok(CronExpression {
+            minutes: Self::parse_field(minutes, 0, 59)?,
+            hours: Self::parse_field(hours, 0, 23)?,
+            days: Self::parse_field(days, 1, 31)?,
+            months: Self::parse_field(months, 1, 12)?,
+            weekdays: weekdays_allowed,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, SmartHouseError> {
+        let number = |s: &str| s.parse::<u32>().map_err(|_| WrongRequestDataError(SCHEDULE_ERROR));
+        let mut allowed = vec![false; max as usize + 1];
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, number(step)?),
+                None => (part, 1)
+            };
+            let (from, to) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((from, to)) => (number(from)?, number(to)?),
+                // "5/15" - начиная с 5 до конца с шагом 15
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?)
+            };
+            if step == 0 || from < min || from > to || to > max {
+                return Err(WrongRequestDataError(SCHEDULE_ERROR));
+            }
+            for value in (from..=to).step_by(step as usize) {
+                allowed[value as usize] = true;
+            }
+        }
+        Ok(allowed)
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.months[date.month() as usize] {
+            return false;
+        }
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday
+        }
+    }
+
+    fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        for day in 0..MAX_CRON_DAYS {
+            let date = start.date() + Days::new(day);
+            if !self.matches_date(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|h| self.hours[*h as usize]) {
+                for minute in (0..60).filter(|m| self.minutes[*m as usize]) {
+                    let at = date.and_hms_opt(hour, minute, 0)?;
+                    if at >= start {
+                        return Some(at);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+// Задание: в моменты из расписания переключить розетку в заданное состояние.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id : u64,
+    pub room : String,
+    pub device : String,
+    pub state : bool,
+    pub schedule : Schedule,
+    /*
+        Ближайший запуск; пропущенный, пока сервер не работал, выполняется один раз при первой проверке.
+        В задании, написанном в файле вручную, его можно не указывать - он посчитается при загрузке.
+     */
+    #[serde(default)]
+    pub next_run : Option<NaiveDateTime>,
+}
+
+/*
+    Планировщик переключения розеток. Задания сохраняются в JSON файл после каждого
+    изменения списка и каждого запуска, чтобы после перезапуска сервера
+    не потерять ни сами задания, ни то, какие запуски уже выполнены.
+ */
+pub struct Scheduler {
+    jobs : Vec<Job>,
+    clock : Box<dyn Clock>,
+    storage : Option<PathBuf>,
+}
+
+impl Scheduler {
+    pub fn new(clock: Box<dyn Clock>) -> Self {
+        Scheduler { jobs: vec![], clock, storage: None }
+    }
+
+    /*
+        Если файла ещё нет, планировщик начинает с пустым списком и создаст его при первом изменении.
+        Задания из файла проверяются так же, как добавленные через `add_job`: файл с неверным
+        расписанием, повторяющимся id или одноразовым заданием без будущего запуска не загружается.
+     */
+    pub fn load(path: &Path, clock: Box<dyn Clock>) -> Result<Self, SmartHouseError> {
+        let mut jobs : Vec<Job> = if path.exists() {
+            let data = fs::read_to_string(path).map_err(StorageError)?;
+            serde_json::from_str(&data).map_err(SerializationError)?
+        } else {
+            vec![]
+        };
+        let now = clock.now();
+        for job in jobs.iter_mut() {
+            job.schedule.validate()?;
+            if job.next_run.is_none() {
+                job.next_run = Some(job.schedule.next_after(now).ok_or(WrongRequestDataError(SCHEDULE_ERROR))?);
+            }
+        }
+        let mut ids = jobs.iter().map(|j| j.id).collect::<Vec<u64>>();
+        ids.sort();
+        ids.dedup();
+        if ids.len() != jobs.len() {
+            return Err(WrongRequestDataError(SCHEDULE_ERROR));
+        }
+        Ok(Scheduler { jobs, clock, storage: Some(PathBuf::from(path)) })
+    }
+
+    pub fn get_jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn add_job(&mut self, room: &str, device: &str, state: bool, schedule: Schedule) -> Result<u64, SmartHouseError> {
+        schedule.validate()?;
+        let next_run = schedule.next_after(self.clock.now())
+            .ok_or(WrongRequestDataError(SCHEDULE_ERROR))?;
+        let id = self.jobs.iter().map(|j| j.id + 1).max().unwrap_or(1);
+        self.jobs.push(Job {
+            id,
+            room: String::from(room),
+            device: String::from(device),
+            state,
+            schedule,
+            next_run: Some(next_run),
+        });
+        self.save()?;
+        Ok(id)
+    }
+
+    pub fn remove_job(&mut self, id: u64) -> Result<Job, SmartHouseError> {
+        let index = self.jobs.iter().position(|j| j.id == id)
+            .ok_or(WrongRequestDataError(JOB_ERROR))?;
+        let job = self.jobs.remove(index);
+        self.save()?;
+        Ok(job)
+    }
+
+    /*
+        Выполняет задания, время которых наступило, в порядке этого времени, чтобы при
+        пропущенных запусках победило более позднее, и возвращает их идентификаторы.
+        Ошибка переключения (например, розетку удалили) только печатается: задание
+        переходит к следующему запуску. Одноразовые задания после запуска удаляются.
+     */
+    pub fn run_pending(&mut self, smart_house: &mut SmartHouse) -> Vec<u64> {
+        let now = self.clock.now();
+        let mut due = self.jobs.iter_mut()
+            .filter(|j| j.next_run.is_some_and(|at| at <= now))
+            .collect::<Vec<&mut Job>>();
+        due.sort_by_key(|j| j.next_run);
+        let mut done = vec![];
+        for job in due {
+            if let Err(e) = smart_house.switch_socket(&job.room, &job.device, job.state) {
+                println!("scheduled job {} failed: {e}", job.id);
+            }
+            job.next_run = job.schedule.next_after(now);
+            done.push(job.id);
+        }
+        if done.is_empty() {
+            return done;
+        }
+        self.jobs.retain(|j| j.next_run.is_some());
+        if let Err(e) = self.save() {
+            println!("could not save scheduled jobs: {e}");
+        }
+        done
+    }
+
+    fn save(&self) -> Result<(), SmartHouseError> {
+        let Some(path) = &self.storage else {
+            return Ok(());
+        };
+        let data = serde_json::to_string_pretty(&self.jobs).map_err(SerializationError)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data).map_err(StorageError)?;
+        fs::rename(&tmp_path, path).map_err(StorageError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+    use crate::device_registry::{DeviceConfig, SOCKET_KIND};
+    use crate::scheduler::{ManualClock, Schedule, Scheduler};
+    use crate::smart_house::SmartHouse;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 - понедельник
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_schedules() {
+        let six = NaiveTime::from_hms_opt(6, 0, 0).unwrap();
+        let weekdays = Schedule::Cron(String::from("0 6 * * 1-5"));
+        assert_eq!(weekdays.next_after(at(1, 5, 0)), Some(at(1, 6, 0)));
+        assert_eq!(weekdays.next_after(at(1, 6, 0)), Some(at(2, 6, 0)));
+        // пятница -> понедельник
+        assert_eq!(weekdays.next_after(at(5, 7, 0)), Some(at(8, 6, 0)));
+        let weekly = Schedule::Weekly { days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri], time: six };
+        assert_eq!(weekly.next_after(at(5, 7, 0)), Some(at(8, 6, 0)));
+        assert_eq!(Schedule::Daily(six).next_after(at(6, 6, 30)), Some(at(7, 6, 0)));
+        assert_eq!(Schedule::Once(at(3, 12, 0)).next_after(at(3, 12, 0)), None);
+
+        let every_quarter = Schedule::Cron(String::from("*/15 8-9 * * *"));
+        assert_eq!(every_quarter.next_after(at(1, 8, 50)), Some(at(1, 9, 0)));
+        assert_eq!(every_quarter.next_after(at(1, 9, 45)), Some(at(2, 8, 0)));
+        // ограничены и день месяца, и день недели: подходит любой
+        let first_or_sunday = Schedule::Cron(String::from("30 22 1 * 0,7"));
+        assert_eq!(first_or_sunday.next_after(at(1, 23, 0)), Some(at(7, 22, 30)));
+
+        for wrong in ["0 6 * *", "60 6 * * *", "0 6 * * 8", "0 6-5 * * *", "*/0 * * * *", "a * * * *"] {
+            assert!(Schedule::Cron(String::from(wrong)).validate().is_err(), "{wrong}");
+        }
+        assert!(Schedule::Weekly { days: vec![], time: six }.validate().is_err());
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket_Boiler")).unwrap();
+        let path = env::temp_dir().join(format!("smart_house_jobs_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // воскресенье вечером
+        let clock = ManualClock::new(at(7, 20, 0));
+        let mut scheduler = Scheduler::load(&path, Box::new(clock.clone())).unwrap();
+        let on = scheduler.add_job("room1", "Socket_Boiler", true, Schedule::Cron(String::from("0 6 * * 1-5"))).unwrap();
+        let off = scheduler.add_job("room1", "Socket_Boiler", false, Schedule::Once(at(8, 7, 0))).unwrap();
+        assert!(scheduler.add_job("room1", "Socket_Boiler", false, Schedule::Once(at(7, 19, 0))).is_err());
+        assert!(scheduler.run_pending(&mut smart_house).is_empty());
+
+        clock.set(at(8, 6, 0));
+        assert_eq!(scheduler.run_pending(&mut smart_house), vec![on]);
+        assert!(smart_house.is_switched_on("room1", "Socket_Boiler").unwrap());
+        assert!(scheduler.run_pending(&mut smart_house).is_empty());
+
+        // задания переживают перезапуск вместе с уже выполненными запусками
+        let mut scheduler = Scheduler::load(&path, Box::new(clock.clone())).unwrap();
+        assert_eq!(scheduler.get_jobs().len(), 2);
+        assert!(scheduler.run_pending(&mut smart_house).is_empty());
+
+        // сервер "проспал" и 07:00, и 06:00 вторника: каждое задание выполняется один раз
+        clock.set(at(9, 6, 30));
+        assert_eq!(scheduler.run_pending(&mut smart_house), vec![off, on]);
+        assert!(smart_house.is_switched_on("room1", "Socket_Boiler").unwrap());
+        assert_eq!(scheduler.get_jobs().len(), 1);
+        assert_eq!(scheduler.get_jobs()[0].next_run, Some(at(10, 6, 0)));
+
+        assert!(scheduler.remove_job(off).is_err());
+        scheduler.remove_job(on).unwrap();
+        assert!(Scheduler::load(&path, Box::new(clock.clone())).unwrap().get_jobs().is_empty());
+
+        // задание, написанное вручную без ближайшего запуска, получает его при загрузке
+        fs::write(&path, r#"[{ "id": 3, "room": "room1", "device": "Socket_Boiler", "state": false,
+                               "schedule": { "Daily": "22:00:00" } }]"#).unwrap();
+        let scheduler = Scheduler::load(&path, Box::new(clock.clone())).unwrap();
+        assert_eq!(scheduler.get_jobs()[0].next_run, Some(at(9, 22, 0)));
+        let wrong = [
+            r#"[{ "id": 1, "room": "room1", "device": "Socket_Boiler", "state": true, "schedule": { "Cron": "0 25 * * *" } }]"#,
+            r#"[{ "id": 1, "room": "room1", "device": "Socket_Boiler", "state": true, "schedule": { "Once": "2024-01-01T06:00:00" } }]"#,
+            r#"[{ "id": 1, "room": "room1", "device": "Socket_Boiler", "state": true, "schedule": { "Daily": "06:00:00" } },
+                { "id": 1, "room": "room1", "device": "Socket_Boiler", "state": false, "schedule": { "Daily": "07:00:00" } }]"#,
+        ];
+        for jobs in wrong {
+            fs::write(&path, jobs).unwrap();
+            assert!(Scheduler::load(&path, Box::new(clock.clone())).is_err(), "{jobs}");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+// датчик, от которого пришло голое значение в 4 байта без идентификатора
+pub const LEGACY_SENSOR_ID : u32 = 0;
+// id (4) + номер (4) + время в мс от начала эпохи (8) + температура (4), всё big-endian
+pub const READING_SIZE : usize = 20;
+const LEGACY_READING_SIZE : usize = 4;
+// на сколько номеров назад датаграмма ещё считается опоздавшей, а не первой после перезапуска датчика
+pub const REORDER_WINDOW : u32 = 16;
+pub const DEFAULT_STALE_SECS : u64 = 30;
+pub const DEFAULT_OFFLINE_SECS : u64 = 120;
+
+/*
+    Показание удалённого UDP датчика. Номер растёт с каждой отправкой и позволяет отбросить
+    датаграммы, пришедшие не по порядку или повторно. Время отправки берётся с часов датчика,
+    которым нельзя доверять, поэтому для порядка оно не используется.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub sensor_id : u32,
+    pub seq : u32,
+    pub timestamp : SystemTime,
+    pub temperature : f32,
+}
+
+// Какой термометр в какой комнате получает показания датчика.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorBinding {
+    pub sensor_id : u32,
+    pub room : String,
+    pub device : String,
+}
+
+/*
+    Состояние датчика по тому, как давно от него было последнее показание:
+    устаревшее показание ещё отдаётся клиентам с пометкой, показание отключённого - нет.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SensorHealth {
+    Online,
+    Stale,
+    Offline,
+}
+
+// Сколько молчания датчика считается устаревшим показанием и сколько - отключением.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensorTimeouts {
+    pub stale_after : Duration,
+    pub offline_after : Duration,
+}
+
+// Температура вместе с тем, когда пришло показание; у термометров, которые меряют сами, `last_seen` нет.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureStatus {
+    pub temperature : f32,
+    pub last_seen : Option<SystemTime>,
+    pub health : SensorHealth,
+}
+
+// Что дом знает о датчике: привязку и последнее принятое показание.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorStatus {
+    pub sensor_id : u32,
+    pub binding : Option<SensorBinding>,
+    pub last_reading : Option<SensorReading>,
+    // время приёма последнего показания, а не время его отправки
+    pub last_seen : Option<SystemTime>,
+    pub health : SensorHealth,
+}
+
+impl SensorTimeouts {
+    // от датчика, который ни разу не присылал показаний, ничего не ждём
+    pub fn health(&self, last_seen: Option<SystemTime>) -> SensorHealth {
+        let Some(last_seen) = last_seen else {
+            return SensorHealth::Offline;
+        };
+        // часы могли перевести назад, тогда показание считается свежим
+        let silence = last_seen.elapsed().unwrap_or_default();
+        if silence >= self.offline_after {
+            SensorHealth::Offline
+        } else if silence >= self.stale_after {
+            SensorHealth::Stale
+        } else {
+            SensorHealth::Online
+        }
+    }
+}
+
+impl Default for SensorTimeouts {
+    fn default() -> Self {
+        SensorTimeouts {
+            stale_after: Duration::from_secs(DEFAULT_STALE_SECS),
+            offline_after: Duration::from_secs(DEFAULT_OFFLINE_SECS),
+        }
+    }
+}
+
+impl Display for SensorHealth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SensorHealth::Online => write!(f, "online"),
+            SensorHealth::Stale => write!(f, "stale"),
+            SensorHealth::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+impl SensorReading {
+    pub fn new(sensor_id: u32, seq: u32, temperature: f32) -> Self {
+        SensorReading { sensor_id, seq, timestamp: SystemTime::now(), temperature }
+    }
+
+    pub fn encode(&self) -> [u8; READING_SIZE] {
+        let millis = self.timestamp.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut buf = [0u8; READING_SIZE];
+        buf[0..4].copy_from_slice(&self.sensor_id.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        buf[8..16].copy_from_slice(&millis.to_be_bytes());
+        buf[16..20].copy_from_slice(&self.temperature.to_be_bytes());
+        buf
+    }
+
+    // старые датчики присылают только температуру: она считается показанием `LEGACY_SENSOR_ID`, полученным сейчас
+    pub fn decode(buf: &[u8]) -> Result<Self, io::Error> {
+        match buf.len() {
+            LEGACY_READING_SIZE => {
+                let temperature = f32::from_be_bytes(buf.try_into().unwrap());
+                Ok(Self::new(LEGACY_SENSOR_ID, 0, temperature))
+            }
+            READING_SIZE => {
+                let u32_at = |i: usize| u32::from_be_bytes(buf[i..i + 4].try_into().unwrap());
+                let millis = u64::from_be_bytes(buf[8..16].try_into().unwrap());
+                Ok(SensorReading {
+                    sensor_id: u32_at(0),
+                    seq: u32_at(4),
+                    timestamp: UNIX_EPOCH + Duration::from_millis(millis),
+                    temperature: f32::from_be_bytes(buf[16..20].try_into().unwrap()),
+                })
+            }
+            len => Err(io::Error::new(io::ErrorKind::InvalidData, format!("wrong sensor datagram size {len}")))
+        }
+    }
+
+    /*
+        Повтор и номер не больше чем на `REORDER_WINDOW` назад - опоздавшая датаграмма. Номер дальше
+        назад значит, что датчик перезапустился и нумерует заново. Разность берётся по модулю 2^32,
+        так что переполнение номера тоже считается движением вперёд. У старых датчиков номеров нет,
+        каждое их показание новое.
+     */
+    pub fn is_newer_than(&self, other: &SensorReading) -> bool {
+        self.sensor_id == LEGACY_SENSOR_ID || other.seq.wrapping_sub(self.seq) > REORDER_WINDOW
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::sensor::{LEGACY_SENSOR_ID, REORDER_WINDOW, SensorReading};
+
+    #[test]
+    fn test_encode_and_decode_readings() {
+        let reading = SensorReading {
+            sensor_id: 7,
+            seq: 42,
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            temperature: 21.5,
+        };
+        assert_eq!(SensorReading::decode(&reading.encode()).unwrap(), reading);
+
+        let legacy = SensorReading::decode(&23.5f32.to_be_bytes()).unwrap();
+        assert_eq!((legacy.sensor_id, legacy.temperature), (LEGACY_SENSOR_ID, 23.5));
+        assert!(SensorReading::decode(&[0u8; 7]).is_err());
+
+        let mut next = reading.clone();
+        next.seq += 1;
+        assert!(next.is_newer_than(&reading));
+        assert!(!reading.is_newer_than(&reading));
+        // перезапущенный датчик начал нумерацию заново
+        next.seq = 0;
+        assert!(next.is_newer_than(&reading));
+        let mut late = reading.clone();
+        late.seq -= REORDER_WINDOW;
+        assert!(!late.is_newer_than(&reading));
+        next.seq = u32::MAX;
+        let mut wrapped = next.clone();
+        wrapped.seq = 0;
+        assert!(wrapped.is_newer_than(&next));
+
+        // показание с часами, ушедшими в будущее, не блокирует следующие
+        let mut future = reading.clone();
+        future.timestamp += Duration::from_secs(365 * 24 * 60 * 60);
+        next = reading.clone();
+        next.seq += 1;
+        assert!(next.is_newer_than(&future));
+        // часы датчика перевели назад, а номер продолжает расти
+        next.timestamp -= Duration::from_secs(3600);
+        assert!(next.is_newer_than(&reading));
+
+        assert!(legacy.is_newer_than(&legacy.clone()));
+    }
+}
@@ -1,84 +1,129 @@
-use std::io::{ ErrorKind, Read, Write};
-use std::net::{TcpListener, TcpStream, UdpSocket};
-use std::sync::{Arc, mpsc, Mutex};
-use std::{io, thread};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, mpsc, Mutex, PoisonError};
+use std::sync::mpsc::RecvTimeoutError;
+use std::{io, panic, thread};
 use std::ops::DerefMut;
+use std::panic::AssertUnwindSafe;
 use ErrorKind::*;
 use std::time::Duration;
-use crate::{END_MESSAGING_COMMAND, START_MESSAGING_COMMAND};
+use std::path::{Path, PathBuf};
+use crate::codec::{read_frame, write_frame};
+use crate::config::{DEFAULT_IDLE_TIMEOUT_SECS, ServerConfig};
+use crate::events::{HouseEvent, SUBSCRIBER_QUEUE_SIZE};
+use crate::protocol::{handle_legacy_message, handle_message, is_legacy_frame, is_subscribed, ServerMessage};
 use crate::errors::SmartHouseError;
+use crate::history::DEFAULT_POWER_SAMPLE_SECS;
+use crate::scheduler::{SCHEDULER_TICK, Scheduler, SystemClock};
+use crate::poller::UdpPoller;
 use crate::smart_house::SmartHouse;
 
+// как часто проверять, не закрыл ли подписчик соединение, пока событий нет
+const EVENT_POLL_INTERVAL : Duration = Duration::from_secs(1);
+
 pub struct Server {
-    pub smart_house : SmartHouse
+    pub smart_house : SmartHouse,
+    // файл, в который сохраняется дом после каждого обработанного запроса
+    pub storage : Option<PathBuf>,
+    // соединение, по которому столько времени не было запросов, закрывается
+    pub idle_timeout : Duration,
+    // переключает розетки по расписанию, пока сервер работает
+    pub scheduler : Option<Scheduler>,
+    // как часто записывать мощность устройств в историю; `None` - не записывать
+    pub power_sampling : Option<Duration>,
 }
 
 impl Server {
 
-    pub fn start(self, own_addr: &str, pool_size: usize, remote_addr: &'static str) {
+    pub fn new(smart_house: SmartHouse) -> Self {
+        let idle_timeout = Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS);
+        let power_sampling = Some(Duration::from_secs(DEFAULT_POWER_SAMPLE_SECS));
+        Server { smart_house, storage: None, idle_timeout, scheduler: None, power_sampling }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SmartHouseError> {
+        let mut server = Self::new(SmartHouse::load(path)?);
+        server.storage = Some(PathBuf::from(path));
+        Ok(server)
+    }
+
+    pub fn from_config(config: &ServerConfig) -> Result<Self, SmartHouseError> {
+        let smart_house = config.build_house()?;
+        let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+        let scheduler = config.build_scheduler(Box::new(SystemClock))?;
+        let power_sampling = config.power_sample_interval();
+        Ok(Server { smart_house, storage: config.storage.clone(), idle_timeout, scheduler, power_sampling })
+    }
+
+    pub fn start_from_config(config: ServerConfig) -> Result<(), SmartHouseError> {
+        let server = Self::from_config(&config)?;
+        let remote_addrs = config.remote_addrs.iter()
+            .map(|a| a.as_str())
+            .collect::<Vec<&str>>();
+        server.start(&config.listen_addr, config.pool_size, &remote_addrs);
+        Ok(())
+    }
+
+    pub fn start(self, own_addr: &str, pool_size: usize, remote_addrs: &[&str]) {
 
         let listener = TcpListener::bind(own_addr).unwrap();
-        let pool = ThreadPool::new(pool_size);
-        let arc = Arc::new(Mutex::new(self.smart_house));
-        let arc_remote = arc.clone();
-
-        /*
-            В отдельном потоке пробуем соединиться с удаленным сервером, пока соединение не будет
-        установлено, после того как оно установилось, в цикле раз в 2 секунды опрашиваем сервер.
-        Если соединение обрывается - выходим из цикла и устанавливаем соединение снова.
-         */
-        thread::spawn(move || {
-            println!("thread for requesting remote server started");
-            loop {
-                let mut connection = UdpSocket::bind(remote_addr);
-                let mut udp_socket = loop {
-                    match connection {
-                        Ok(udp_socket) => {
-                            break udp_socket;
-                        }
-                        Err(err) => {
-                            println!("Trying to get connection to remote server failed: {err}");
-                            thread::sleep(Duration::from_secs(1));
-                            connection = UdpSocket::bind(remote_addr);
-                        }
-                    }
-                };
-                loop {
-                    let remote_data = Self::get_remote_thermo_data(&mut udp_socket);
-                    if let Ok(temperature) = remote_data {
-                        println!("remote data (temperature): {temperature}");
-                        arc_remote.lock().unwrap().deref_mut().set_thermo_data(temperature);
-                    }
-                    else {
-                        let error = remote_data.err().unwrap();
-                        println!("error while requesting remote data... {}", &error);
-                        match error.kind() {
-                            // здесь должны по-разному обрабатываться ошибки
-                            NotFound | ConnectionRefused | ConnectionReset | PermissionDenied
-                            | HostUnreachable | NetworkUnreachable | ConnectionAborted |
-                            NotConnected | AddrInUse | AddrNotAvailable | TimedOut | InvalidData
-                            | Other | UnexpectedEof | NetworkDown => {
-                                println!("error kind : {}", &error.kind());
-                                return;
-                            }
-                            _ => {
-                                println!("error kind : {}", &error.kind());
-                                return;
-                            }
-                        }
-                    }
-                    thread::sleep(Duration::from_secs(2));
+        let pool = Arc::new(ThreadPool::new(pool_size));
+        // планировщик живёт в доме, чтобы клиенты могли менять задания командами протокола
+        let mut smart_house = self.smart_house;
+        if let Some(scheduler) = self.scheduler {
+            smart_house.set_scheduler(scheduler);
+        }
+        let scheduled = smart_house.get_jobs().is_ok();
+        let arc = Arc::new(Mutex::new(smart_house));
+        let storage = self.storage;
+        let idle_timeout = self.idle_timeout;
+
+        // показания удалённых датчиков принимаются на каждом адресе в отдельном потоке
+        for remote_addr in remote_addrs {
+            let mut poller = UdpPoller::new(remote_addr);
+            // розетки, переключённые правилами по показанию, иначе потерялись бы при перезапуске
+            let storage = storage.clone();
+            poller.on_rules_applied = Some(Box::new(move |house| Self::save(house, storage.as_deref())));
+            let arc_remote = arc.clone();
+            thread::spawn(move || poller.run(arc_remote));
+        }
+
+        // задания выполняются под той же блокировкой, что и запросы клиентов
+        if scheduled {
+            let arc = arc.clone();
+            let storage = storage.clone();
+            thread::spawn(move || loop {
+                thread::sleep(SCHEDULER_TICK);
+                let mut smart_house = arc.lock().unwrap_or_else(PoisonError::into_inner);
+                if !smart_house.run_scheduled_jobs().is_empty() {
+                    smart_house.apply_rules();
+                    Self::save(&mut smart_house, storage.as_deref());
                 }
-            }
-        });
+            });
+        }
+
+        if let Some(period) = self.power_sampling {
+            let arc = arc.clone();
+            thread::spawn(move || loop {
+                thread::sleep(period);
+                arc.lock().unwrap_or_else(PoisonError::into_inner).record_power();
+            });
+        }
 
         println!("server started");
         for stream in listener.incoming() {
-            let stream = stream.unwrap();
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("could not accept connection: {e}");
+                    continue;
+                }
+            };
             let arc = arc.clone();
-            pool.execute( move || {
-                Self::handle_connection(arc, stream)
-            });
+            let storage = storage.clone();
+            let pool = pool.clone();
+            // соединение живёт долго и почти всё время ждёт кадр, поэтому ждёт его в своём потоке
+            thread::spawn(move || Self::handle_connection(arc, stream, storage, idle_timeout, pool));
         }
     }
 
@@ -86,134 +131,139 @@ impl Server {
         self.smart_house.get_thermo_data()
     }
 
-    fn handle_connection(smart_house: Arc<Mutex<SmartHouse>>, mut stream: TcpStream) {
-        println!("new request is processing...");
-        let mut buf = [0; 4];
-        stream.read_exact(&mut buf).expect("could not read bite buf");
-        let len = u32::from_be_bytes(buf);
-        let mut buf = vec![0; len as _];
-
-        stream.read_exact(&mut buf).expect("could not read bite buf");
-
-        let commands = String::from_utf8(buf).unwrap()
-            .split('\n')
-            .map(String::from)
-            .collect::<Vec<String>>();
-
-        if !commands.get(0).unwrap().contains(START_MESSAGING_COMMAND) {
-            println!("wrong start command...");
+    /*
+        Соединение обслуживает запросы один за другим, пока клиент его не закроет
+        или пока за `idle_timeout` не придёт ни одного кадра. Поток пула занят только
+        обработкой запроса, между кадрами он свободен для других соединений.
+     */
+    fn handle_connection(smart_house: Arc<Mutex<SmartHouse>>, mut stream: TcpStream, storage: Option<PathBuf>,
+                         idle_timeout: Duration, pool: Arc<ThreadPool>) {
+        println!("new connection is processing...");
+        if let Err(e) = stream.set_read_timeout(Some(idle_timeout)) {
+            println!("could not set idle timeout: {e}");
             return;
-        };
-
-        let command = commands.get(1).unwrap();
-        println!("command: {command}" );
-
-        let args = commands.iter()
-            .skip(3)
-            .take_while(|c| !c.eq(&&END_MESSAGING_COMMAND))
-            .map(|s| s.as_str())
-            .flat_map(|s| s.split(' '))
-            .filter(|e| e.ne(&String::from("").as_str()))
-            .collect::<Vec<&str>>();
+        }
+        loop {
+            let buf = match read_frame(&mut stream) {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == UnexpectedEof => {
+                    println!("client closed connection");
+                    return;
+                }
+                Err(e) if matches!(e.kind(), WouldBlock | TimedOut) => {
+                    println!("connection closed after idle timeout");
+                    return;
+                }
+                Err(e) => {
+                    println!("could not read request: {e}");
+                    return;
+                }
+            };
 
-        let lock = smart_house.lock();
-        if let Ok(mut lock) = lock {
-            let res = Self::command(lock.deref_mut(), &mut stream, command.as_str(), args);
-            if res.is_err() {
-                Self::send_bytes("unable to process request...".as_bytes(),&mut stream).expect("error");
+            let (sender, receiver) = mpsc::channel();
+            let smart_house = smart_house.clone();
+            let storage = storage.clone();
+            pool.execute(move || {
+                let _ = sender.send(Self::process_frame(&smart_house, &buf, storage.as_deref()));
+            });
+            // задание запаниковало и ответа не будет
+            let Ok((reply, events)) = receiver.recv() else {
+                println!("could not process request");
+                return;
+            };
+
+            let sent = reply
+                .and_then(|bytes| Self::send_bytes(&bytes, &mut stream).map_err(SmartHouseError::from));
+            if let Err(e) = sent {
+                println!("error while sending response : {e}");
+                return;
+            }
+            if let Some(events) = events {
+                Self::send_events(stream, events);
+                return;
             }
-        } else {
-            println!("could not get lock, lock is poisoned!");
         }
     }
 
-    fn get_remote_thermo_data(udp_socket: &mut UdpSocket) -> Result<f32, io::Error> {
-        let buf: &mut [u8; 4] = &mut Default::default();
-        udp_socket.recv(buf)?;
-        let vec = buf.to_vec();
-        let mut data: [u8; 4] = Default::default();
-        for (i,e) in vec.iter().enumerate() {
-            data[i] = *e;
+    // ответ на кадр и подписка, если клиент подписался
+    fn process_frame(smart_house: &Mutex<SmartHouse>, buf: &[u8], storage: Option<&Path>)
+        -> (Result<Vec<u8>, SmartHouseError>, Option<mpsc::Receiver<HouseEvent>>) {
+
+        // мьютекс мог быть отравлен паникой в другом потоке, данные дома при этом остаются целыми
+        let mut lock = smart_house.lock().unwrap_or_else(PoisonError::into_inner);
+        let revision = lock.get_revision();
+        let mut events = None;
+        let reply = if is_legacy_frame(buf) {
+            Ok(handle_legacy_message(lock.deref_mut(), buf).into_bytes())
+        } else {
+            let message = handle_message(lock.deref_mut(), buf);
+            // подписываемся под той же блокировкой, чтобы не пропустить ни одного события
+            if is_subscribed(&message) {
+                let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE_SIZE);
+                lock.subscribe(move |event| sender.try_send(event.clone()).is_ok());
+                events = Some(receiver);
+            }
+            message.encode()
+        };
+        // запросы на чтение дом не меняют, переписывать файл после них незачем
+        if lock.get_revision() != revision {
+            Self::save(lock.deref_mut(), storage);
         }
-        let temperature = f32::from_be_bytes(data);
-        Ok(temperature)
+        (reply, events)
     }
 
-    fn command(smart_house : &mut SmartHouse,
-               stream: &mut TcpStream,
-               command : &str,
-               args : Vec<&str>)
-               -> Result <(), io::Error>
-    {
-        let stream = stream;
-
-        match command {
-            crate::SWITCH_SOCKET_COMMAND => {
-                let room_name = *args.get(0).unwrap();
-                let device_name = *args.get(1).unwrap();
-                let is_on = *args.get(2).unwrap();
-                let is_on = match is_on {
-                    "true" => true,
-                    "t" => true,
-                    "false" => false,
-                    "f" => false,
-                    _ => false
-                };
-                let result = Self::switch_socket(smart_house, room_name, device_name, is_on);
-                match result {
-                    Ok(_) => {
-                        let ok_str = String::from(crate::OK_RESPONSE);
-                        let buf = ok_str.as_bytes();
-                        Self::send_bytes(buf, stream)?;
-                        Ok(())
-                    }
-                    Err(_) => {
-                        let err_str = String::from(crate::ERR_RESPONSE);
-                        let buf = err_str.as_bytes();
-                        Self::send_bytes(buf, stream)?;
-                        Ok(())
+    /*
+        Доставка событий подписчику, пока он не закроет соединение. Когда поток выходит,
+        `receiver` пропадает и слушатель в доме отписывается при следующем событии.
+        Если очередь подписчика переполнилась, слушатель отписывается сам, и после
+        уже стоящих в очереди событий соединение закрывается.
+     */
+    fn send_events(mut stream: TcpStream, receiver: mpsc::Receiver<HouseEvent>) {
+        println!("client subscribed to events");
+        loop {
+            match receiver.recv_timeout(EVENT_POLL_INTERVAL) {
+                Ok(event) => {
+                    let sent = ServerMessage::Event { event }.encode()
+                        .and_then(|bytes| Self::send_bytes(&bytes, &mut stream).map_err(SmartHouseError::from));
+                    if let Err(e) = sent {
+                        println!("subscriber disconnected : {e}");
+                        return;
                     }
                 }
-            },
-            crate::GET_SOCKET_CONSUMED_POWER => {
-                let room_name = *args.get(0).unwrap();
-                let device_name = *args.get(1).unwrap();
-                let socket_state = Self::get_socket_state(smart_house, room_name, device_name);
-                match socket_state {
-                    Ok(power) => {
-                        Self::send_bytes(power.to_string().as_bytes(), stream)?;
-                        Ok(())
-                    },
-                    Err(_) => {
-                        Self::send_bytes(String::from(crate::ERR_RESPONSE).as_bytes(), stream)?;
-                        Ok(())
-                    }
+                // событий давно не было - проверяем, что клиент ещё на связи
+                Err(RecvTimeoutError::Timeout) if !Self::is_closed(&stream) => {}
+                Err(_) => {
+                    println!("subscriber disconnected");
+                    return;
                 }
-            },
-            _ => todo!(),
+            }
         }
     }
 
-    fn switch_socket(smart_house: &mut SmartHouse, room: &str, device : &str, is_on : bool)
-        -> Result<bool, SmartHouseError>
-    {
-        smart_house.switch_socket( room,  device, is_on)
+    fn is_closed(stream: &TcpStream) -> bool {
+        if stream.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let closed = match stream.peek(&mut [0; 1]) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) => e.kind() != WouldBlock,
+        };
+        closed || stream.set_nonblocking(false).is_err()
     }
 
-    fn get_socket_state (smart_house: &mut SmartHouse, room: &str, device : &str)
-        -> Result<f32, SmartHouseError>
-    {
-        smart_house.get_socket_state(room, device)
+    fn save(smart_house: &mut SmartHouse, storage: Option<&Path>) {
+        if let Some(path) = storage {
+            if let Err(e) = smart_house.save(path) {
+                println!("could not save smart house to {}: {e}", path.display());
+            }
+        }
     }
 
     fn send_bytes(data: &[u8], stream: &mut TcpStream)
         -> Result<(), io::Error>
     {
-        let len = data.len() as u32;
-        let len_bytes = len.to_be_bytes();
-        stream.write_all(&len_bytes)?;
-        stream.write_all(data)?;
-        Ok(())
+        write_frame(stream, data)
     }
 }
 
@@ -260,11 +310,164 @@ struct Worker {
 impl Worker {
     fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
         let thread = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
+            let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+            // пул уничтожен, заданий больше не будет
+            let Ok(job) = job else { break };
             println!("Worker {id} got a job; executing.");
-            job();
+            // паника в задании не должна останавливать поток пула
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                println!("Worker {id}: job panicked");
+            }
         });
         Worker { id, thread }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::{Arc, mpsc, Mutex};
+    use std::sync::mpsc::TryRecvError;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+    use crate::Command;
+    use crate::client::Client;
+    use crate::events::SUBSCRIBER_QUEUE_SIZE;
+    use crate::history::{History, HistoryQuery, SeriesKey};
+    use crate::protocol::{ClientMessage, PROTOCOL_VERSION};
+    use crate::server::{Server, ThreadPool};
+    use crate::smart_house::SmartHouse;
+
+    #[test]
+    fn test_lagging_subscriber_is_dropped() {
+        let smart_house = Mutex::new(SmartHouse::new("SmartHouse", vec!["hall"]));
+        let subscribe = ClientMessage::Request { version: PROTOCOL_VERSION, id: 1, command: Command::Subscribe }
+            .encode().unwrap();
+        let (reply, events) = Server::process_frame(&smart_house, &subscribe, None);
+        assert!(reply.is_ok());
+        let events = events.expect("no subscription");
+
+        // подписчик ничего не читает, а события продолжают приходить
+        for i in 0..=SUBSCRIBER_QUEUE_SIZE {
+            smart_house.lock().unwrap().set_thermo_data(i as f32 + 1.0);
+        }
+        for _ in 0..SUBSCRIBER_QUEUE_SIZE {
+            assert!(events.try_recv().is_ok());
+        }
+        // переполнившая очередь подписка снята, новых событий не будет
+        assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Disconnected);
+    }
+
+    #[test]
+    fn test_worker_survives_panicking_job() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(|| panic!("job failed"));
+        pool.execute(move || sender.send(42).unwrap());
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(42));
+    }
+
+    #[test]
+    fn test_connection_serves_requests_until_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let smart_house = Arc::new(Mutex::new(SmartHouse::new("SmartHouse", vec!["hall"])));
+        let connection = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let pool = Arc::new(ThreadPool::new(1));
+            Server::handle_connection(smart_house, stream, None, Duration::from_millis(200), pool);
+        });
+
+        // рукопожатие и несколько запросов по одному соединению
+        let mut client = Client::connect(addr).unwrap();
+        for _ in 0..3 {
+            assert_eq!(client.get_rooms().unwrap(), vec!["hall"]);
+        }
+
+        // без запросов дольше `idle_timeout` сервер закрывает соединение
+        let started = Instant::now();
+        connection.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(client.get_rooms().is_err());
+    }
+
+    // сервер в тесте запускается в другом потоке и начинает слушать не сразу
+    fn connect(addr: &str) -> Client {
+        let started = Instant::now();
+        loop {
+            match Client::connect(addr) {
+                Ok(client) => return client,
+                Err(_) if started.elapsed() < Duration::from_secs(5) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("could not connect: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_subscribers_do_not_occupy_pool() {
+        let pool_size = 2;
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let server = Server::new(SmartHouse::new("SmartHouse", vec!["hall"]));
+        let server_addr = addr.clone();
+        thread::spawn(move || server.start(&server_addr, pool_size, &[]));
+        let mut subscriptions = (0..pool_size)
+            .map(|_| connect(&addr).subscribe().unwrap())
+            .collect::<Vec<_>>();
+
+        // все потоки пула свободны, хотя подписчиков столько же, сколько потоков;
+        // иначе запрос ждал бы в очереди пула, пока подписчик не отключится
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(connect(&addr).add_room("kitchen").is_ok()));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(true));
+        for subscription in subscriptions.iter_mut() {
+            assert!(subscription.next().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_more_clients_than_pool_threads() {
+        let pool_size = 1;
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let server = Server::new(SmartHouse::new("SmartHouse", vec!["hall"]));
+        let server_addr = addr.clone();
+        thread::spawn(move || server.start(&server_addr, pool_size, &[]));
+
+        // открытые соединения не держат поток пула, поэтому обслуживаются все клиенты по очереди
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut clients = (0..3).map(|_| connect(&addr)).collect::<Vec<Client>>();
+            for _ in 0..2 {
+                for client in clients.iter_mut() {
+                    assert_eq!(client.get_rooms().unwrap(), vec!["hall"]);
+                }
+            }
+            sender.send(true).unwrap();
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(true));
+    }
+
+    #[test]
+    fn test_day_of_history_fits_in_reply() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut history = History::new(Duration::from_secs(60), day);
+        let series = SeriesKey::Sensor(1);
+        let now = SystemTime::now();
+        for minute in 0..24 * 60 {
+            history.record(series.clone(), now - Duration::from_secs(minute * 60), 20.0);
+        }
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["hall"]);
+        smart_house.set_history(history);
+
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let server = Server::new(smart_house);
+        let server_addr = addr.clone();
+        thread::spawn(move || server.start(&server_addr, 1, &[]));
+
+        // график за сутки: ответ помещается в кадр, а значения не теряются при укрупнении шага
+        let mut client = connect(&addr);
+        let buckets = client.get_history(HistoryQuery::last(series, day, Duration::from_secs(60))).unwrap();
+        assert!(!buckets.is_empty());
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<u32>(), 24 * 60);
+        assert_eq!(client.get_rooms().unwrap(), vec!["hall"]);
+    }
+}
@@ -1,11 +1,46 @@
 use std::collections::HashMap;
-use crate::device_info_provider::{Device, DeviceInfoProvider, SmartSocket, SmartThermometer};
-use crate::errors::{DEVICE_ERROR, ROOM_ERROR, SmartHouseError};
+use crate::automation::Rule;
+use crate::device_info_provider::{Capability, Device, DeviceInfoProvider, TemperatureSensing};
+use crate::device_registry::{DeviceConfig, DeviceRegistry};
+use crate::errors::{DEVICE_ERROR, DeviceError, NO_SCHEDULER_ERROR, NO_TEMPERATURE_ERROR, ROOM_ERROR, ROOM_EXISTS_ERROR,
+                    GROUP_ERROR, RULE_ERROR, SCENE_ERROR, SENSOR_OFFLINE_ERROR, SmartHouseError};
+use crate::errors::SmartHouseError::WrongRequestDataError;
+use crate::events::{EventBus, HouseChange, HouseEvent, ListenerId};
+use crate::group::{DeviceGroup, MemberResult};
+use crate::history::{Bucket, History, HistoryQuery, SeriesKey};
+use crate::poller::PollerStatus;
+use crate::report::{HouseReport, RoomReport};
+use crate::scene::Scene;
+use crate::scheduler::{Job, Schedule, Scheduler};
+use crate::sensor::{LEGACY_SENSOR_ID, SensorBinding, SensorHealth, SensorReading, SensorStatus, SensorTimeouts,
+                    TemperatureStatus};
+use crate::storage::{DeviceState, HouseState, RoomState};
+use std::path::Path;
+use std::time::SystemTime;
+
+const MAX_RULE_PASSES : usize = 10;
 
 pub struct SmartHouse {
     name : String,
     rooms: HashMap<String, Room>,
-    remote_thermo: Box<f32>
+    remote_thermo: Box<f32>,
+    registry: DeviceRegistry,
+    events: EventBus,
+    rules: Vec<Rule>,
+    scenes: Vec<Scene>,
+    groups: Vec<DeviceGroup>,
+    sensors: Vec<SensorBinding>,
+    // последнее принятое показание каждого датчика, в том числе не привязанного к термометру, и время его приёма
+    readings: HashMap<u32, (SensorReading, SystemTime)>,
+    // когда пришло последнее общее значение удалённых датчиков
+    thermo_updated_at: Option<SystemTime>,
+    sensor_timeouts: SensorTimeouts,
+    // состояние потоков приёма показаний, их публикуют сами потоки
+    pollers: Vec<PollerStatus>,
+    // показания датчиков и мощность устройств за последнее время, только в памяти
+    history: History,
+    // переключение розеток по расписанию; без планировщика команды заданий возвращают ошибку
+    scheduler: Option<Scheduler>,
 }
 
 pub struct Room {
@@ -14,6 +49,11 @@ pub struct Room {
     pub devices : HashMap<String, Box<dyn Device>>,
 }
 
+// Представление дома только для чтения: отдаёт живое состояние устройств из `rooms`.
+pub struct SmartHouseView<'a> {
+    smart_house : &'a SmartHouse,
+}
+
 impl SmartHouse {
 
     pub fn new(name : &str, rooms_names : Vec<&str>) -> Self {
@@ -37,27 +77,136 @@ impl SmartHouse {
         SmartHouse {
             name : own_name,
             rooms,
-            remote_thermo
+            remote_thermo,
+            registry: DeviceRegistry::default(),
+            events: EventBus::default(),
+            rules: vec![],
+            scenes: vec![],
+            groups: vec![],
+            sensors: vec![],
+            readings: HashMap::new(),
+            thermo_updated_at: None,
+            sensor_timeouts: SensorTimeouts::default(),
+            pollers: vec![],
+            history: History::default(),
+            scheduler: None,
         }
     }
 
+    pub fn with_registry(name : &str, rooms_names : Vec<&str>, registry: DeviceRegistry) -> Self {
+        let mut smart_house = Self::new(name, rooms_names);
+        smart_house.registry = registry;
+        smart_house
+    }
+
+    pub fn register_device_kind<F>(&mut self, kind: &str, factory: F)
+        where
+            F: Fn(&DeviceConfig) -> Box<dyn Device> + Send + Sync + 'static,
+    {
+        self.registry.register(kind, factory);
+    }
+
+    pub fn get_device_kinds(&self) -> Vec<&str> {
+        self.registry.get_kinds()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SmartHouseError> {
+        Self::load_with_registry(path, DeviceRegistry::default())
+    }
+
+    pub fn load_with_registry(path: &Path, registry: DeviceRegistry) -> Result<Self, SmartHouseError> {
+        Self::from_state(&HouseState::read(path)?, registry)
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<(), SmartHouseError> {
+        self.to_state().write(path)
+    }
+
+    pub fn from_state(state: &HouseState, registry: DeviceRegistry) -> Result<Self, SmartHouseError> {
+        let mut smart_house = Self::with_registry(&state.name, vec![], registry);
+        for room in &state.rooms {
+            smart_house.add_room(&room.name)?;
+            for device in &room.devices {
+                let config = DeviceConfig {
+                    name: device.name.clone(),
+                    is_on: device.is_on,
+                    power: device.power
+                };
+                smart_house.add_device(&room.name, &device.kind, config)?;
+                let restored = smart_house.get_device_mut(&room.name, &device.name)?;
+                if let (Some(brightness), Some(dimmable)) = (device.brightness, restored.as_dimmable_mut()) {
+                    dimmable.set_brightness(brightness);
+                }
+                if let (Some(energy), Some(metered)) = (device.energy, restored.as_power_metered_mut()) {
+                    metered.set_consumed_energy(energy);
+                }
+            }
+        }
+        // устройства сцены могли удалить после её создания, это проверяется при применении
+        smart_house.scenes = state.scenes.clone();
+        smart_house.groups = state.groups.clone();
+        Ok(smart_house)
+    }
+
+    pub fn to_state(&mut self) -> HouseState {
+        let mut rooms: Vec<RoomState> = self.rooms.values_mut()
+            .map(|room| room.to_state())
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        HouseState { name: self.name.clone(), rooms, scenes: self.scenes.clone(), groups: self.groups.clone() }
+    }
+
+    /*
+        Подписка на изменения в доме. Слушатель вызывается сразу после изменения, в том же
+        потоке и под той же блокировкой, поэтому должен быть быстрым; вернув `false`, он отписывается.
+     */
+    pub fn subscribe<F>(&mut self, listener: F) -> ListenerId
+        where
+            F: FnMut(&HouseEvent) -> bool + Send + 'static,
+    {
+        self.events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    // растёт с каждым изменением дома: если после запроса она та же, сохранять дом незачем
+    pub fn get_revision(&self) -> u64 {
+        self.events.get_revision()
+    }
+
+    pub fn view(&self) -> SmartHouseView<'_> {
+        SmartHouseView { smart_house: self }
+    }
+
     pub fn get_rooms(&self) -> Vec<&str> {
         let mut result = Vec::new();
         self.rooms.iter().for_each(|r| result.push(r.0.as_str()));
         result
     }
 
-    pub fn add_room(&mut self, room_name : &str) {
+    // существующая комната не заменяется пустой: её устройства пропали бы молча
+    pub fn add_room(&mut self, room_name : &str) -> Result<bool, SmartHouseError> {
+        if self.rooms.contains_key(room_name) {
+            return Err(WrongRequestDataError(ROOM_EXISTS_ERROR));
+        }
         let room = Room { name: String::from(room_name), devices: HashMap::new() };
-        let room_name = String::from(room_name);
-        self.rooms.insert(room_name, room);
+        self.rooms.insert(String::from(room_name), room);
+        self.events.publish(HouseChange::RoomAdded { room: String::from(room_name) });
+        Ok(true)
     }
 
     pub fn remove_room(&mut self, room_name : &str) -> Result<bool, SmartHouseError> {
         let remove = self.rooms.remove(room_name);
         match remove {
             None => { Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR)) }
-            Some(_) => { Ok(true) }
+            Some(mut room) => {
+                self.groups.iter_mut().for_each(|g| { g.remove_room(room_name); });
+                self.history.remove_room(room_name);
+                self.events.publish(HouseChange::RoomRemoved { room: String::from(room_name), before: room.to_state() });
+                Ok(true)
+            }
         }
     }
 
@@ -71,110 +220,649 @@ impl SmartHouse {
         Some(devices)
     }
 
-    pub fn add_device(&mut self, room_name: &str, device_name: &str) -> Result<bool, SmartHouseError> {
-        let rooms = &mut self.rooms;
-        let mut is_added = false;
-        rooms.iter_mut()
-            .filter(|r| r.1.name.eq(room_name))
-            .for_each(|r| {
-                if device_name.contains("Socket") {
-                    let device = SmartSocket { is_on: false, name: device_name.to_string() };
-                    r.1.devices.insert(device_name.to_string(), Box::new(device));
-                    is_added = true;
-                } else if device_name.contains("Thermo") {
-                    let device = SmartThermometer { is_on: false, name: device_name.to_string()};
-                    r.1.devices.insert(device_name.to_string(), Box::new(device));
-                    is_added = true;
-                };
-        });
-        if is_added { Ok (true) }
-        else { Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR)) }
+    pub fn add_device(&mut self, room_name: &str, device_kind: &str, config: DeviceConfig)
+        -> Result<bool, SmartHouseError>
+    {
+        let room = match self.rooms.get_mut(room_name) {
+            Some(room) => room,
+            None => return Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR))
+        };
+        let mut device = self.registry.create(device_kind, &config)?;
+        let after = device_state(device.as_mut());
+        room.devices.insert(config.name, device);
+        self.events.publish(HouseChange::DeviceAdded { room: String::from(room_name), after });
+        Ok(true)
     }
 
     pub fn remove_device(&mut self, room_name: &str, device_name: &str) -> Result<bool, SmartHouseError> {
-        let rooms = &mut self.rooms;
-        if !rooms.contains_key(room_name) {
-            return Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR))
-        }
-        for r in rooms {
-            if r.0.eq(room_name) {
-                let room = r.1;
-                room.devices.remove(device_name);
-                return Ok(true)
-            }
-        }
-        Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))
+        let room = self.rooms.get_mut(room_name)
+            .ok_or(WrongRequestDataError(ROOM_ERROR))?;
+        let mut device = room.devices.remove(device_name)
+            .ok_or(WrongRequestDataError(DEVICE_ERROR))?;
+        self.groups.iter_mut().for_each(|g| { g.remove_member(room_name, device_name); });
+        self.history.remove_device(room_name, device_name);
+        let before = device_state(device.as_mut());
+        self.events.publish(HouseChange::DeviceRemoved { room: String::from(room_name), before });
+        Ok(true)
     }
 
-    pub fn create_report(
+    pub fn create_device_report(
         &self,
-        /* todo: принять обобщённый тип предоставляющий информацию об устройствах */
         device_info_provider : &dyn DeviceInfoProvider,
         room_name : &str,
         device_name : &str
     ) -> Result <String, SmartHouseError, > {
-        // todo!("перебор комнат и устройств в них для составления отчёта")
         device_info_provider.get_device_state(room_name, device_name)
     }
 
+    pub fn create_report(&mut self) -> HouseReport {
+        let timeouts = self.sensor_timeouts;
+        let rooms = self.rooms.values_mut()
+            .map(|room| {
+                let devices = room.devices.values_mut()
+                    .map(|device| {
+                        let mut report = device.get_report();
+                        if report.last_seen.is_some() {
+                            report.health = Some(timeouts.health(report.last_seen));
+                        }
+                        report
+                    })
+                    .collect();
+                RoomReport::new(&room.name, devices)
+            })
+            .collect();
+        let mut report = HouseReport::new(&self.name, rooms, *self.remote_thermo);
+        report.remote_last_seen = self.thermo_updated_at;
+        report.remote_health = self.thermo_updated_at.map(|t| timeouts.health(Some(t)));
+        report
+    }
+
     pub fn switch_socket(&mut self, room_name: &str, device_name : &str, state : bool)
         -> Result<bool, SmartHouseError>
     {
-        let room_opt = self.rooms.get_mut(room_name);
-        let room: &mut Room;
-
-        match room_opt {
-            Some(..) => { room = room_opt.unwrap() },
-            None => { return Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR))}
-        };
-
-        let device_opt = room.devices.get_mut(device_name);
-
-        match device_opt {
-            Some(dev) => {
-                dev.switch_on_off(state);
+        let device = self.get_device_mut(room_name, device_name)?;
+        match device.as_switchable_mut() {
+            Some(switchable) => {
+                let before = switchable.is_on();
+                switchable.switch_on_off(state);
+                let after = switchable.is_on();
+                self.events.publish(HouseChange::SocketSwitched {
+                    room: String::from(room_name),
+                    device: String::from(device_name),
+                    before,
+                    after,
+                });
                 Ok(true)
             },
-            None => Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))
+            None => Err(unsupported(Capability::Switchable))
         }
     }
 
+    pub fn is_switched_on(&self, room_name: &str, device_name : &str) -> Result<bool, SmartHouseError> {
+        self.get_device(room_name, device_name)?
+            .as_switchable()
+            .map(|switchable| switchable.is_on())
+            .ok_or(unsupported(Capability::Switchable))
+    }
+
     pub fn get_socket_state(&mut self, room_name: &str, device_name : &str)
         -> Result<f32, SmartHouseError>
     {
-        let room_opt = self.rooms.get_mut(room_name);
-        let room: &mut Room;
+        let device = self.get_device_mut(room_name, device_name)?;
+        match device.as_power_metered_mut() {
+            Some(metered) => Ok(metered.get_consumed_power()),
+            None => Err(unsupported(Capability::PowerMetered))
+        }
+    }
 
-        match room_opt {
-            Some(..) => { room = room_opt.unwrap() },
-            None => { return Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR))}
-        };
+    pub fn get_socket_energy(&mut self, room_name: &str, device_name : &str)
+        -> Result<f64, SmartHouseError>
+    {
+        let device = self.get_device_mut(room_name, device_name)?;
+        match device.as_power_metered_mut() {
+            Some(metered) => Ok(metered.get_consumed_energy()),
+            None => Err(unsupported(Capability::PowerMetered))
+        }
+    }
+
+    pub fn reset_socket_energy(&mut self, room_name: &str, device_name : &str)
+        -> Result<bool, SmartHouseError>
+    {
+        let device = self.get_device_mut(room_name, device_name)?;
+        match device.as_power_metered_mut() {
+            Some(metered) => {
+                let before = metered.get_consumed_energy();
+                metered.reset_energy();
+                self.events.publish(HouseChange::EnergyReset {
+                    room: String::from(room_name),
+                    device: String::from(device_name),
+                    before,
+                });
+                Ok(true)
+            },
+            None => Err(unsupported(Capability::PowerMetered))
+        }
+    }
 
-        let device_opt = room.devices.get_mut(device_name);
+    // суммарная мощность устройств комнаты, которые её потребляют
+    pub fn get_room_power(&mut self, room_name: &str) -> Result<f32, SmartHouseError> {
+        let room = self.rooms.get_mut(room_name)
+            .ok_or(WrongRequestDataError(ROOM_ERROR))?;
+        let power = room.devices.values_mut()
+            .filter_map(|d| d.as_power_metered_mut())
+            .map(|m| m.get_consumed_power())
+            .sum();
+        Ok(power)
+    }
 
-        match device_opt {
-            Some(dev) => {
-                let power = dev.get_consumed_power(device_name);
-                Ok(power)
+    pub fn set_brightness(&mut self, room_name: &str, device_name : &str, brightness : u8)
+        -> Result<bool, SmartHouseError>
+    {
+        let device = self.get_device_mut(room_name, device_name)?;
+        match device.as_dimmable_mut() {
+            Some(dimmable) => {
+                let before = dimmable.get_brightness();
+                dimmable.set_brightness(brightness);
+                let after = dimmable.get_brightness();
+                self.events.publish(HouseChange::BrightnessChanged {
+                    room: String::from(room_name),
+                    device: String::from(device_name),
+                    before,
+                    after,
+                });
+                Ok(true)
             },
-            None => Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))
+            None => Err(unsupported(Capability::Dimmable))
         }
     }
 
+    pub fn get_capabilities(&mut self, room_name: &str, device_name : &str)
+        -> Result<Vec<Capability>, SmartHouseError>
+    {
+        Ok(self.get_device_mut(room_name, device_name)?.get_capabilities())
+    }
+
+    /*
+        Общее значение удалённых датчиков получают все удалённые термометры, кроме привязанных
+        к своему датчику через `bind_sensor`: у них показания только от него.
+        В истории общее значение записывается как показание `LEGACY_SENSOR_ID`.
+     */
     pub fn set_thermo_data(&mut self, data: f32) {
+        self.history.record(SeriesKey::Sensor(LEGACY_SENSOR_ID), SystemTime::now(), data);
+        self.update_thermo_data(data);
+    }
+
+    fn update_thermo_data(&mut self, data: f32) {
+        let before = *self.remote_thermo;
         *self.remote_thermo = data;
+        self.thermo_updated_at = Some(SystemTime::now());
+        if before != data {
+            self.events.publish(HouseChange::ThermoDataChanged { before, after: data });
+        }
+        let sensors = &self.sensors;
+        self.rooms.iter_mut()
+            .flat_map(|(room, r)| r.devices.iter_mut().map(move |(device, d)| (room, device, d)))
+            .filter(|(room, device, _)| !sensors.iter().any(|s| s.room == **room && s.device == **device))
+            .filter_map(|(_, _, d)| d.as_temperature_sensing_mut())
+            .for_each(|t| t.set_remote_temperature(data));
+    }
+
+    // привязка с тем же id заменяется; устройство должно измерять температуру
+    pub fn bind_sensor(&mut self, binding: SensorBinding) -> Result<bool, SmartHouseError> {
+        self.get_device(&binding.room, &binding.device)?
+            .as_temperature_sensing()
+            .ok_or(unsupported(Capability::TemperatureSensing))?;
+        self.sensors.retain(|s| s.sensor_id != binding.sensor_id);
+        self.sensors.push(binding);
+        Ok(true)
+    }
+
+    pub fn get_sensor_bindings(&self) -> &[SensorBinding] {
+        &self.sensors
+    }
+
+    /*
+        Принимает показание удалённого датчика. Показание привязанного датчика получает только
+        его термометр, не привязанного - весь дом, как `set_thermo_data`. Если термометр привязки
+        удалили, показание всё равно принимается и попадает в историю, но никому не передаётся.
+        Показания, пришедшие не по порядку или повторно, отбрасываются, тогда возвращается `false`.
+     */
+    pub fn set_sensor_reading(&mut self, reading: SensorReading) -> bool {
+        if let Some((last, _)) = self.readings.get(&reading.sensor_id) {
+            if !reading.is_newer_than(last) {
+                return false;
+            }
+        }
+        let binding = self.sensors.iter()
+            .find(|s| s.sensor_id == reading.sensor_id)
+            .cloned();
+        match binding {
+            Some(binding) => {
+                let thermometer = self.get_device_mut(&binding.room, &binding.device).ok()
+                    .and_then(|d| d.as_temperature_sensing_mut());
+                if let Some(thermometer) = thermometer {
+                    let before = thermometer.get_temperature();
+                    thermometer.set_remote_temperature(reading.temperature);
+                    if before != Some(reading.temperature) {
+                        self.events.publish(HouseChange::TemperatureChanged {
+                            room: binding.room,
+                            device: binding.device,
+                            before,
+                            after: reading.temperature,
+                        });
+                    }
+                }
+            }
+            None => self.update_thermo_data(reading.temperature),
+        }
+        let received_at = SystemTime::now();
+        self.history.record(SeriesKey::Sensor(reading.sensor_id), received_at, reading.temperature);
+        self.readings.insert(reading.sensor_id, (reading, received_at));
+        true
+    }
+
+    pub fn set_history(&mut self, history: History) {
+        self.history = history;
+    }
+
+    // записывает в историю текущую мощность всех устройств, которые её потребляют
+    pub fn record_power(&mut self) {
+        let now = SystemTime::now();
+        for (room_name, room) in self.rooms.iter_mut() {
+            for (device_name, device) in room.devices.iter_mut() {
+                if let Some(metered) = device.as_power_metered_mut() {
+                    let series = SeriesKey::Power { room: room_name.clone(), device: device_name.clone() };
+                    self.history.record(series, now, metered.get_consumed_power());
+                }
+            }
+        }
+    }
+
+    // история мощности запрашивается у существующего устройства, история датчика - у любого id
+    pub fn get_history(&self, query: &HistoryQuery) -> Result<Vec<Bucket>, SmartHouseError> {
+        if let SeriesKey::Power { room, device } = &query.series {
+            self.get_device(room, device)?;
+        }
+        self.history.query(query)
+    }
+
+    pub fn set_poller_status(&mut self, status: PollerStatus) {
+        match self.pollers.iter_mut().find(|p| p.addr == status.addr) {
+            Some(poller) => *poller = status,
+            None => self.pollers.push(status),
+        }
+    }
+
+    pub fn get_poller_statuses(&self) -> &[PollerStatus] {
+        &self.pollers
+    }
+
+    pub fn set_sensor_timeouts(&mut self, timeouts: SensorTimeouts) {
+        self.sensor_timeouts = timeouts;
+    }
+
+    // привязанные датчики и датчики, от которых приходили показания, по возрастанию id
+    pub fn get_sensors(&self) -> Vec<SensorStatus> {
+        let mut ids = self.sensors.iter()
+            .map(|s| s.sensor_id)
+            .chain(self.readings.keys().copied())
+            .collect::<Vec<u32>>();
+        ids.sort();
+        ids.dedup();
+        ids.into_iter()
+            .map(|sensor_id| {
+                let received = self.readings.get(&sensor_id);
+                let last_seen = received.map(|(_, received_at)| *received_at);
+                SensorStatus {
+                    sensor_id,
+                    binding: self.sensors.iter().find(|s| s.sensor_id == sensor_id).cloned(),
+                    last_reading: received.map(|(reading, _)| reading.clone()),
+                    last_seen,
+                    health: self.sensor_timeouts.health(last_seen),
+                }
+            })
+            .collect()
+    }
+
+    // средняя температура по термометрам комнаты, у которых уже есть показания
+    pub fn get_room_temperature(&self, room_name: &str) -> Result<f32, SmartHouseError> {
+        let room = self.rooms.get(room_name)
+            .ok_or(WrongRequestDataError(ROOM_ERROR))?;
+        let temperatures = room.devices.values()
+            .filter_map(|d| d.as_temperature_sensing())
+            .filter_map(|t| self.fresh_temperature(t).ok())
+            .map(|s| s.temperature)
+            .collect::<Vec<f32>>();
+        if temperatures.is_empty() {
+            return Err(SmartHouseError::CommandError(DeviceError::ThermoError(NO_TEMPERATURE_ERROR)));
+        }
+        Ok(temperatures.iter().sum::<f32>() / temperatures.len() as f32)
     }
 
     pub fn get_thermo_data(& self) -> f32 {
         *self.remote_thermo
     }
+
+    // общее значение удалённых датчиков, если оно приходило и датчики ещё не отключились
+    pub fn get_thermo_status(&self) -> Result<TemperatureStatus, SmartHouseError> {
+        if self.thermo_updated_at.is_none() {
+            return Err(SmartHouseError::CommandError(DeviceError::ThermoError(NO_TEMPERATURE_ERROR)));
+        }
+        self.checked_status(*self.remote_thermo, self.thermo_updated_at)
+    }
+
+    pub fn get_remote_temperature(&self) -> Result<f32, SmartHouseError> {
+        self.get_thermo_status().map(|s| s.temperature)
+    }
+
+    pub fn get_temperature_status(&self, room_name: &str, device_name: &str)
+        -> Result<TemperatureStatus, SmartHouseError>
+    {
+        let sensing = self.get_device(room_name, device_name)?
+            .as_temperature_sensing()
+            .ok_or(unsupported(Capability::TemperatureSensing))?;
+        self.fresh_temperature(sensing)
+    }
+
+    pub fn get_temperature(&self, room_name: &str, device_name: &str)
+        -> Result<f32, SmartHouseError>
+    {
+        self.get_temperature_status(room_name, device_name).map(|s| s.temperature)
+    }
+
+    fn fresh_temperature(&self, sensing: &dyn TemperatureSensing) -> Result<TemperatureStatus, SmartHouseError> {
+        let temperature = sensing.get_temperature()
+            .ok_or(SmartHouseError::CommandError(DeviceError::ThermoError(NO_TEMPERATURE_ERROR)))?;
+        self.checked_status(temperature, sensing.last_updated())
+    }
+
+    // показание удалённого датчика, который давно молчит, не выдаётся за текущую температуру
+    fn checked_status(&self, temperature: f32, last_seen: Option<SystemTime>)
+        -> Result<TemperatureStatus, SmartHouseError>
+    {
+        // термометр, который меряет сам, всегда на связи
+        let health = match last_seen {
+            Some(_) => self.sensor_timeouts.health(last_seen),
+            None => SensorHealth::Online,
+        };
+        if health == SensorHealth::Offline {
+            return Err(SmartHouseError::CommandError(DeviceError::ThermoError(SENSOR_OFFLINE_ERROR)));
+        }
+        Ok(TemperatureStatus { temperature, last_seen, health })
+    }
+
+    pub fn set_rules(&mut self, rules: Vec<Rule>) {
+        self.rules = rules;
+    }
+
+    pub fn get_rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn set_rule_enabled(&mut self, rule_name: &str, enabled: bool) -> Result<bool, SmartHouseError> {
+        let rule = self.rules.iter_mut()
+            .find(|r| r.name == rule_name)
+            .ok_or(WrongRequestDataError(RULE_ERROR))?;
+        rule.enabled = enabled;
+        // включённое правило должно проверяться сразу, как после любого изменения дома
+        self.events.mark_changed();
+        Ok(true)
+    }
+
+    /*
+        Применяет включённые правила, пока они что-то меняют (одно правило может выключать
+        розетки по одной за проход), но не больше `MAX_RULE_PASSES` раз, чтобы правила,
+        противоречащие друг другу, не зациклились. Возвращает имена сработавших правил.
+     */
+    pub fn apply_rules(&mut self) -> Vec<String> {
+        let mut triggered = Vec::new();
+        let rules = self.rules.clone();
+        for _ in 0..MAX_RULE_PASSES {
+            let mut changed = false;
+            for rule in &rules {
+                match rule.evaluate(self) {
+                    Ok(true) => {
+                        changed = true;
+                        triggered.push(rule.name.clone());
+                        self.events.publish(HouseChange::RuleTriggered { rule: rule.name.clone() });
+                    }
+                    Ok(false) => {}
+                    Err(e) => println!("rule {} failed: {e}", rule.name),
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        triggered
+    }
+
+    pub fn get_scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    // сцена с тем же именем заменяется; все устройства сцены должны существовать и переключаться
+    pub fn add_scene(&mut self, scene: Scene) -> Result<bool, SmartHouseError> {
+        for (room, device, _) in scene.targets() {
+            self.is_switched_on(room, device)?;
+        }
+        self.scenes.retain(|s| s.name != scene.name);
+        self.scenes.push(scene);
+        self.events.mark_changed();
+        Ok(true)
+    }
+
+    /*
+        Применяет сцену целиком или никак. Сначала запоминаются текущие состояния всех её
+        устройств - заодно это проверяет, что комнаты и устройства ещё существуют и переключаются.
+        Если переключение всё же не удалось, уже переключённые устройства возвращаются
+        в прежнее состояние в обратном порядке, а ошибка отдаётся вызывающему.
+     */
+    pub fn apply_scene(&mut self, scene_name: &str) -> Result<bool, SmartHouseError> {
+        let scene = self.scenes.iter()
+            .find(|s| s.name == scene_name)
+            .cloned()
+            .ok_or(WrongRequestDataError(SCENE_ERROR))?;
+        let targets = scene.targets();
+        let before = targets.iter()
+            .map(|(room, device, _)| self.is_switched_on(room, device))
+            .collect::<Result<Vec<bool>, SmartHouseError>>()?;
+        for (applied, (room, device, state)) in targets.iter().enumerate() {
+            if let Err(e) = self.switch_socket(room, device, *state) {
+                for ((room, device, _), state) in targets[..applied].iter().zip(&before).rev() {
+                    let _ = self.switch_socket(room, device, *state);
+                }
+                return Err(e);
+            }
+        }
+        self.events.publish(HouseChange::SceneApplied { scene: scene.name });
+        Ok(true)
+    }
+
+    pub fn get_groups(&self) -> &[DeviceGroup] {
+        &self.groups
+    }
+
+    // группа с тем же именем заменяется; все её устройства должны существовать
+    pub fn add_group(&mut self, group: DeviceGroup) -> Result<bool, SmartHouseError> {
+        for (room, device) in group.get_members() {
+            self.get_device(room, device)?;
+        }
+        self.groups.retain(|g| g.name != group.name);
+        self.groups.push(group);
+        self.events.mark_changed();
+        Ok(true)
+    }
+
+    pub fn remove_group(&mut self, group_name: &str) -> Result<bool, SmartHouseError> {
+        let index = self.groups.iter()
+            .position(|g| g.name == group_name)
+            .ok_or(WrongRequestDataError(GROUP_ERROR))?;
+        self.groups.remove(index);
+        self.events.mark_changed();
+        Ok(true)
+    }
+
+    /*
+        Групповые команды выполняются для каждого устройства группы отдельно: ошибка одного
+        устройства (например, термометр нельзя переключить) не мешает остальным
+        и возвращается в его результате. Ошибка всей команды - только если нет такой группы.
+     */
+    pub fn switch_group(&mut self, group_name: &str, state: bool)
+        -> Result<Vec<MemberResult<bool>>, SmartHouseError>
+    {
+        self.for_each_member(group_name, |smart_house, room, device| {
+            smart_house.switch_socket(room, device, state)
+        })
+    }
+
+    pub fn get_group_power(&mut self, group_name: &str) -> Result<Vec<MemberResult<f32>>, SmartHouseError> {
+        self.for_each_member(group_name, |smart_house, room, device| {
+            smart_house.get_socket_state(room, device)
+        })
+    }
+
+    pub fn set_scheduler(&mut self, scheduler: Scheduler) {
+        self.scheduler = Some(scheduler);
+    }
+
+    pub fn get_jobs(&self) -> Result<&[Job], SmartHouseError> {
+        self.scheduler.as_ref()
+            .map(Scheduler::get_jobs)
+            .ok_or(WrongRequestDataError(NO_SCHEDULER_ERROR))
+    }
+
+    // по расписанию можно переключать только существующую розетку
+    pub fn add_job(&mut self, room_name: &str, device_name: &str, state: bool, schedule: Schedule)
+        -> Result<u64, SmartHouseError>
+    {
+        self.is_switched_on(room_name, device_name)?;
+        self.scheduler_mut()?.add_job(room_name, device_name, state, schedule)
+    }
+
+    pub fn remove_job(&mut self, id: u64) -> Result<Job, SmartHouseError> {
+        self.scheduler_mut()?.remove_job(id)
+    }
+
+    // выполняет задания, время которых наступило, и возвращает их идентификаторы
+    pub fn run_scheduled_jobs(&mut self) -> Vec<u64> {
+        // заданиям нужен весь дом, поэтому на время запуска планировщик вынимается из него
+        let Some(mut scheduler) = self.scheduler.take() else {
+            return vec![];
+        };
+        let done = scheduler.run_pending(self);
+        self.scheduler = Some(scheduler);
+        done
+    }
+
+    fn scheduler_mut(&mut self) -> Result<&mut Scheduler, SmartHouseError> {
+        self.scheduler.as_mut().ok_or(WrongRequestDataError(NO_SCHEDULER_ERROR))
+    }
+
+    fn for_each_member<T, F>(&mut self, group_name: &str, mut action: F) -> Result<Vec<MemberResult<T>>, SmartHouseError>
+        where
+            F: FnMut(&mut Self, &str, &str) -> Result<T, SmartHouseError>,
+    {
+        let group = self.groups.iter()
+            .find(|g| g.name == group_name)
+            .cloned()
+            .ok_or(WrongRequestDataError(GROUP_ERROR))?;
+        Ok(group.get_members().into_iter()
+            .map(|(room, device)| (String::from(room), String::from(device), action(self, room, device)))
+            .collect())
+    }
+
+    fn get_device(&self, room_name: &str, device_name: &str)
+        -> Result<&dyn Device, SmartHouseError>
+    {
+        let room = self.rooms.get(room_name)
+            .ok_or(WrongRequestDataError(ROOM_ERROR))?;
+        room.devices.get(device_name)
+            .map(|d| d.as_ref())
+            .ok_or(WrongRequestDataError(DEVICE_ERROR))
+    }
+
+    fn get_device_mut(&mut self, room_name: &str, device_name: &str)
+        -> Result<&mut Box<dyn Device>, SmartHouseError>
+    {
+        let room = self.rooms.get_mut(room_name)
+            .ok_or(WrongRequestDataError(ROOM_ERROR))?;
+        room.devices.get_mut(device_name)
+            .ok_or(WrongRequestDataError(DEVICE_ERROR))
+    }
+}
+
+impl Room {
+    pub fn to_state(&mut self) -> RoomState {
+        let mut devices: Vec<DeviceState> = self.devices.values_mut()
+            .map(|device| device_state(device.as_mut()))
+            .collect();
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        RoomState { name: self.name.clone(), devices }
+    }
+}
+
+// снимок устройства: для сохранения дома и для событий о добавлении и удалении
+fn device_state(device: &mut dyn Device) -> DeviceState {
+    let config = device.get_config();
+    DeviceState {
+        name: config.name,
+        kind: String::from(device.get_kind()),
+        is_on: config.is_on,
+        power: config.power,
+        brightness: device.as_dimmable().map(|d| d.get_brightness()),
+        energy: device.as_power_metered_mut().map(|m| m.get_consumed_energy()),
+    }
+}
+
+fn unsupported(capability: Capability) -> SmartHouseError {
+    SmartHouseError::CommandError(DeviceError::UnsupportedError(capability))
+}
+
+
+impl DeviceInfoProvider for SmartHouse {
+    fn get_device_state(&self, room_name: &str, device_name: &str)
+        -> Result<String, SmartHouseError> {
+
+        Ok(self.get_device(room_name, device_name)?.get_state())
+    }
+}
+
+impl<'a> SmartHouseView<'a> {
+    pub fn get_rooms(&self) -> Vec<&str> {
+        self.smart_house.get_rooms()
+    }
+
+    pub fn get_devices(&self, room_name: &str) -> Option<Vec<&str>> {
+        self.smart_house.get_devices(room_name)
+    }
 }
 
+impl<'a> DeviceInfoProvider for SmartHouseView<'a> {
+    fn get_device_state(&self, room_name: &str, device_name: &str)
+        -> Result<String, SmartHouseError> {
+        self.smart_house.get_device_state(room_name, device_name)
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+    use crate::events::{HouseChange, HouseEvent};
+    use crate::storage::HouseState;
     use crate::smart_house::{SmartHouse};
+    use crate::errors::DEVICE_ERROR;
     use crate::device_info_provider::{*};
+    use crate::device_registry::{DeviceConfig, LAMP_KIND, SIMULATED_THERMOMETER_KIND, SOCKET_KIND, THERMOMETER_KIND};
+    use crate::errors::{DEVICE_KIND_ERROR, DeviceError, ROOM_ERROR, ROOM_EXISTS_ERROR, SCENE_ERROR, SENSOR_OFFLINE_ERROR,
+                        SmartHouseError};
+    use crate::group::DeviceGroup;
+    use crate::history::{HistoryQuery, SeriesKey};
+    use crate::scene::Scene;
+    use crate::sensor::{LEGACY_SENSOR_ID, SensorBinding, SensorHealth, SensorReading, SensorTimeouts};
 
 
     #[test]
@@ -187,17 +875,22 @@ mod tests {
         let mut smart_house = SmartHouse::new(name, room_names);
         //assert_eq!(smart_house.get_rooms(), vec!["room1", "room2"]);
 
-        smart_house.add_room("room3");
-        smart_house.add_room("room4");
+        smart_house.add_room("room3").expect("error adding room");
+        smart_house.add_room("room4").expect("error adding room");
+        assert!(matches!(smart_house.add_room("room3"),
+                         Err(SmartHouseError::WrongRequestDataError(ROOM_EXISTS_ERROR))));
         assert!(smart_house.get_rooms().contains(&"room3")
             & smart_house.get_rooms().contains(&"room4"));
 
         smart_house.remove_room("room4").expect("error removing room");
         assert!(!smart_house.get_rooms().contains(&"room4"));
 
-        smart_house.add_device("room3", "Socket1").expect("error adding device");
-        smart_house.add_device("room3", "Socket2").expect("error adding device");
-        smart_house.add_device("room3", "Thermo1").expect("error adding device");
+        smart_house.add_device("room3", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+        smart_house.add_device("room3", SOCKET_KIND, DeviceConfig::new("Socket2"))
+            .expect("error adding device");
+        smart_house.add_device("room3", THERMOMETER_KIND, DeviceConfig::new("Thermo1"))
+            .expect("error adding device");
 
         let actual_devices = smart_house.get_devices("room3").unwrap()
             .join(" ");
@@ -207,9 +900,9 @@ mod tests {
         smart_house.remove_device("room3", "Socket2").expect("error removing device");
         assert!(!&smart_house.get_devices("room3").unwrap().contains(&"Socket2"));
 
-        let socket1 = SmartSocket { is_on: false, name: "socket1".to_string() };
-        let socket2 = SmartSocket { is_on: false, name: "socket2".to_string() };
-        let thermo1 = SmartThermometer { is_on: false, name: "thermo1".to_string() };
+        let socket1 = SmartSocket::new("socket1");
+        let socket2 = SmartSocket::new("socket2");
+        let thermo1 = SmartThermometer::new("thermo1", TemperatureSource::Remote);
 
         let info_provider_1 = OwningDeviceInfoProvider {
             name: room1,
@@ -217,24 +910,24 @@ mod tests {
             thermos: vec![thermo1]
         };
 
-        let owning_report = smart_house.create_report(
+        let owning_report = smart_house.create_device_report(
             &(info_provider_1), "room1", "socket2");
         assert_eq!(owning_report.unwrap(), "socket2");
 
-        let socket3 = SmartSocket { is_on: false, name: "socket3".to_string() };
-        let thermo2 = SmartThermometer { is_on: false, name: "thermo2".to_string() };
-        let thermo3 = SmartThermometer { is_on: false, name: "thermo3".to_string() };
+        let socket3 = SmartSocket::new("socket3");
+        let thermo2 = SmartThermometer::new("thermo2", TemperatureSource::Remote);
+        let thermo3 = SmartThermometer::new("thermo3", TemperatureSource::Remote);
 
         let info_provider_2 = BorrowingDeviceInfoProvider {
             name: room2,
             sockets: &vec![socket3],
             thermos: &vec![thermo2, thermo3],
         };
-        let borrowing_report = smart_house.create_report(
+        let borrowing_report = smart_house.create_device_report(
             &(info_provider_2), "room2", "socket3");
         assert_eq!(borrowing_report.unwrap(), "socket3");
 
-        let err_result = smart_house.create_report(
+        let err_result = smart_house.create_device_report(
             &(info_provider_1), "room4", "socket4");
         let err = match err_result {
             Err(..) => err_result.err().unwrap().to_string(),
@@ -243,7 +936,7 @@ mod tests {
 
         //assert_eq!(err, "InnerError has occured! no such room".to_string());
 
-        let err_result1 = smart_house.create_report(
+        let err_result1 = smart_house.create_device_report(
             &(info_provider_2), "room2", "socket4");
 
         let err1 = match err_result1 {
@@ -253,5 +946,509 @@ mod tests {
 
         //assert_eq!(err1, "InnerError has occured! no such device".to_string());
     }
-}
 
+    #[test]
+    fn test_add_device_by_kind() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["kitchen"]);
+
+        smart_house.add_device("kitchen", SOCKET_KIND, DeviceConfig::new("Kitchen kettle"))
+            .expect("error adding device");
+        assert!(smart_house.get_devices("kitchen").unwrap().contains(&"Kitchen kettle"));
+
+        let no_room = smart_house.add_device("hall", SOCKET_KIND, DeviceConfig::new("Lamp"));
+        assert!(matches!(no_room, Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR))));
+
+        let no_kind = smart_house.add_device("kitchen", "kettle", DeviceConfig::new("Kettle"));
+        assert!(matches!(no_kind, Err(SmartHouseError::WrongRequestDataError(DEVICE_KIND_ERROR))));
+
+        smart_house.register_device_kind("kettle", |config| {
+            Box::new(SmartSocket::with_power(&config.name, 2000.0))
+        });
+        smart_house.add_device("kitchen", "kettle", DeviceConfig::new("Kettle"))
+            .expect("error adding device");
+        assert!(smart_house.get_devices("kitchen").unwrap().contains(&"Kettle"));
+    }
+
+    #[test]
+    fn test_create_report() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1", "room2"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+        smart_house.add_device("room1", THERMOMETER_KIND, DeviceConfig::new("Thermo1"))
+            .expect("error adding device");
+        smart_house.add_device("room2", SOCKET_KIND, DeviceConfig::new("Socket2"))
+            .expect("error adding device");
+        smart_house.switch_socket("room1", "Socket1", true).expect("error switching socket");
+        smart_house.set_thermo_data(24.5);
+
+        let report = smart_house.create_report();
+        assert_eq!(report.rooms.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+                   vec!["room1", "room2"]);
+
+        let room1 = &report.rooms[0];
+        assert_eq!(room1.devices.len(), 2);
+        assert_eq!(room1.devices[0].is_on, Some(true));
+        assert_eq!(room1.devices[0].consumed_power, Some(SOCKET_DEFAULT_POWER));
+        assert_eq!(report.rooms[1].devices[0].consumed_power, Some(0.0));
+        assert_eq!(room1.devices[1].is_on, None);
+        assert_eq!(room1.devices[1].consumed_power, Some(THERMOMETER_POWER));
+        assert_eq!(room1.devices[1].temperature, Some(24.5));
+        assert_eq!(room1.total_power, SOCKET_DEFAULT_POWER + THERMOMETER_POWER);
+        assert_eq!(report.total_power, room1.total_power + report.rooms[1].total_power);
+        assert_eq!(report.remote_temperature, 24.5);
+
+        let text = report.to_string();
+        assert!(text.contains("room room1"));
+        assert!(text.contains("Socket1: on"));
+        assert!(text.contains("Thermo1: power 0.50, energy "));
+    }
+
+    #[test]
+    fn test_smart_house_as_info_provider() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+
+        let state = smart_house.create_device_report(&smart_house, "room1", "Socket1");
+        assert_eq!(state.unwrap(), "Socket1: off");
+
+        smart_house.switch_socket("room1", "Socket1", true).expect("error switching socket");
+        let view = smart_house.view();
+        assert_eq!(view.get_device_state("room1", "Socket1").unwrap(), "Socket1: on");
+
+        let no_room = view.get_device_state("room2", "Socket1");
+        assert!(matches!(no_room, Err(SmartHouseError::WrongRequestDataError(ROOM_ERROR))));
+        let no_device = view.get_device_state("room1", "Socket2");
+        assert!(matches!(no_device, Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))));
+    }
+
+    #[test]
+    fn test_thermometer_readings() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", THERMOMETER_KIND, DeviceConfig::new("Thermo1"))
+            .expect("error adding device");
+        smart_house.add_device("room1", SIMULATED_THERMOMETER_KIND, DeviceConfig::new("Thermo2"))
+            .expect("error adding device");
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+
+        let no_data = smart_house.get_temperature("room1", "Thermo1");
+        assert!(matches!(no_data, Err(SmartHouseError::CommandError(DeviceError::ThermoError(_)))));
+
+        smart_house.set_thermo_data(26.0);
+        assert_eq!(smart_house.get_temperature("room1", "Thermo1").unwrap(), 26.0);
+
+        let simulated = smart_house.get_temperature("room1", "Thermo2").unwrap();
+        assert!((20.0..25.0).contains(&simulated));
+        for (min, max) in [(25.0, 25.0), (25.0, 20.0), (f32::MIN, f32::MAX)] {
+            let thermometer = SmartThermometer::new("Thermo3", TemperatureSource::Simulated { min, max });
+            assert_eq!(thermometer.get_temperature(), Some(min));
+        }
+
+        assert_eq!(smart_house.get_socket_state("room1", "Thermo1").unwrap(), THERMOMETER_POWER);
+        assert_eq!(smart_house.get_device_state("room1", "Thermo1").unwrap(),
+                   "Thermo1: temperature 26.0");
+    }
+
+    #[test]
+    fn test_device_capabilities() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+        smart_house.add_device("room1", THERMOMETER_KIND, DeviceConfig::new("Thermo1"))
+            .expect("error adding device");
+        smart_house.add_device("room1", LAMP_KIND, DeviceConfig::new("Lamp1"))
+            .expect("error adding device");
+
+        assert_eq!(smart_house.get_capabilities("room1", "Socket1").unwrap(),
+                   vec![Capability::Switchable, Capability::PowerMetered]);
+        assert_eq!(smart_house.get_capabilities("room1", "Thermo1").unwrap(),
+                   vec![Capability::PowerMetered, Capability::TemperatureSensing]);
+
+        let switch_thermo = smart_house.switch_socket("room1", "Thermo1", true);
+        assert!(matches!(switch_thermo, Err(SmartHouseError::CommandError(
+            DeviceError::UnsupportedError(Capability::Switchable)))));
+        let socket_temperature = smart_house.get_temperature("room1", "Socket1");
+        assert!(matches!(socket_temperature, Err(SmartHouseError::CommandError(
+            DeviceError::UnsupportedError(Capability::TemperatureSensing)))));
+        assert!(smart_house.set_brightness("room1", "Socket1", 50).is_err());
+
+        smart_house.switch_socket("room1", "Lamp1", true).expect("error switching lamp");
+        smart_house.set_brightness("room1", "Lamp1", 50).expect("error dimming lamp");
+        assert_eq!(smart_house.get_socket_state("room1", "Lamp1").unwrap(), LAMP_MAX_POWER / 2.0);
+        assert_eq!(smart_house.get_device_state("room1", "Lamp1").unwrap(),
+                   "Lamp1: on, brightness 50%");
+    }
+
+    #[test]
+    fn test_energy_metering() {
+        let start = Instant::now();
+        let mut meter = EnergyMeter::starting_at(0.0, start);
+        meter.update(0.0, start + Duration::from_secs(3600));
+        assert_eq!(meter.get_energy(), 0.0);
+        meter.update(100.0, start + Duration::from_secs(2 * 3600));
+        assert!((meter.get_energy() - 100.0).abs() < 1e-9);
+        meter.update(50.0, start + Duration::from_secs(2 * 3600 + 1800));
+        assert!((meter.get_energy() - 125.0).abs() < 1e-9);
+        meter.reset(start + Duration::from_secs(3 * 3600));
+        assert_eq!(meter.get_energy(), 0.0);
+
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1"]);
+        smart_house.add_device("room1", SOCKET_KIND, DeviceConfig::new("Socket1"))
+            .expect("error adding device");
+        assert_eq!(smart_house.get_socket_state("room1", "Socket1").unwrap(), 0.0);
+
+        smart_house.switch_socket("room1", "Socket1", true).expect("error switching socket");
+        thread::sleep(Duration::from_millis(20));
+        let energy = smart_house.get_socket_energy("room1", "Socket1").unwrap();
+        assert!(energy > 0.0);
+
+        smart_house.switch_socket("room1", "Socket1", false).expect("error switching socket");
+        let switched_off = smart_house.get_socket_energy("room1", "Socket1").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(smart_house.get_socket_energy("room1", "Socket1").unwrap(), switched_off);
+
+        smart_house.reset_socket_energy("room1", "Socket1").expect("error resetting energy");
+        assert_eq!(smart_house.get_socket_energy("room1", "Socket1").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["room1", "room2"]);
+        let mut config = DeviceConfig::new("Kitchen kettle");
+        config.power = Some(2000.0);
+        smart_house.add_device("room1", SOCKET_KIND, config).expect("error adding device");
+        smart_house.add_device("room1", THERMOMETER_KIND, DeviceConfig::new("Thermo1"))
+            .expect("error adding device");
+        smart_house.add_device("room2", LAMP_KIND, DeviceConfig::new("Lamp1"))
+            .expect("error adding device");
+        smart_house.switch_socket("room1", "Kitchen kettle", true).expect("error switching socket");
+        smart_house.set_brightness("room2", "Lamp1", 30).expect("error dimming lamp");
+        smart_house.add_room("room3").expect("error adding room");
+
+        let path = std::env::temp_dir().join(format!("smart_house_{}.json", std::process::id()));
+        smart_house.save(&path).expect("error saving house");
+        let mut loaded = SmartHouse::load(&path).expect("error loading house");
+        std::fs::remove_file(&path).expect("error removing file");
+
+        let mut rooms = loaded.get_rooms();
+        rooms.sort();
+        assert_eq!(rooms, vec!["room1", "room2", "room3"]);
+        assert_eq!(loaded.get_socket_state("room1", "Kitchen kettle").unwrap(), 2000.0);
+        assert!(loaded.get_socket_energy("room1", "Kitchen kettle").unwrap() > 0.0);
+        assert_eq!(loaded.get_capabilities("room1", "Thermo1").unwrap(),
+                   vec![Capability::PowerMetered, Capability::TemperatureSensing]);
+        assert_eq!(loaded.get_device_state("room2", "Lamp1").unwrap(), "Lamp1: off, brightness 30%");
+
+        let broken = HouseState::read(Path::new("/nonexistent/smart_house.json"));
+        assert!(matches!(broken, Err(SmartHouseError::StorageError(_))));
+    }
+
+    #[test]
+    fn test_house_events() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["hall"]);
+        let (sender, receiver) = mpsc::channel();
+        smart_house.subscribe(move |event| sender.send(event.clone()).is_ok());
+        let started = SystemTime::now();
+
+        smart_house.add_room("kitchen").unwrap();
+        smart_house.add_device("hall", SOCKET_KIND, DeviceConfig::new("Socket")).unwrap();
+        smart_house.switch_socket("hall", "Socket", true).unwrap();
+        smart_house.set_thermo_data(22.0);
+        smart_house.set_thermo_data(22.0);
+        smart_house.remove_device("hall", "Socket").unwrap();
+        assert!(matches!(smart_house.remove_device("hall", "Socket"),
+                         Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))));
+        assert!(smart_house.switch_socket("hall", "Socket", false).is_err());
+        smart_house.remove_room("kitchen").unwrap();
+
+        // чтение и неудачные команды ревизию не меняют, изменения без событий - меняют
+        let revision = smart_house.get_revision();
+        smart_house.create_report();
+        assert!(smart_house.remove_group("heaters").is_err());
+        assert_eq!(smart_house.get_revision(), revision);
+        smart_house.add_group(DeviceGroup::new("heaters")).unwrap();
+        assert_eq!(smart_house.get_revision(), revision + 1);
+
+        let events = receiver.try_iter().collect::<Vec<HouseEvent>>();
+        assert!(events.iter().all(|e| e.timestamp >= started));
+        let changes = events.into_iter().map(|e| e.change).collect::<Vec<HouseChange>>();
+        assert_eq!(changes.len(), 6);
+        assert_eq!(changes[0], HouseChange::RoomAdded { room: String::from("kitchen") });
+        assert!(matches!(&changes[1], HouseChange::DeviceAdded { room, after }
+            if room == "hall" && after.name == "Socket" && after.kind == SOCKET_KIND && !after.is_on));
+        assert_eq!(changes[2], HouseChange::SocketSwitched {
+            room: String::from("hall"), device: String::from("Socket"), before: false, after: true });
+        assert_eq!(changes[3], HouseChange::ThermoDataChanged { before: 0.0, after: 22.0 });
+        assert!(matches!(&changes[4], HouseChange::DeviceRemoved { before, .. } if before.is_on));
+        assert!(matches!(&changes[5], HouseChange::RoomRemoved { before, .. } if before.devices.is_empty()));
+
+        // слушатель, чей канал закрыт, отписывается; остальных можно отписать по id
+        drop(receiver);
+        smart_house.set_thermo_data(23.0);
+        assert!(smart_house.events.is_empty());
+        let id = smart_house.subscribe(|_| true);
+        assert!(smart_house.unsubscribe(id));
+        assert!(!smart_house.unsubscribe(id));
+    }
+
+    // настенный выключатель: его состояние видно, но переключить его удалённо нельзя
+    struct WallSwitch {
+        name : String,
+        is_on : bool,
+    }
+
+    impl Switchable for WallSwitch {
+        fn switch_on_off(&mut self, state: bool) {
+            self.is_on = state;
+        }
+
+        fn is_on(&self) -> bool {
+            self.is_on
+        }
+    }
+
+    impl Device for WallSwitch {
+        fn get_name(&self) -> &str {
+            &self.name
+        }
+
+        fn set_name(&mut self, name: &str) {
+            self.name = String::from(name);
+        }
+
+        fn get_kind(&self) -> &str {
+            "wall_switch"
+        }
+
+        fn as_switchable(&self) -> Option<&dyn Switchable> {
+            Some(self)
+        }
+    }
+
+    #[test]
+    fn test_scenes() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["hall", "kitchen"]);
+        smart_house.add_device("hall", SOCKET_KIND, DeviceConfig::new("Socket")).unwrap();
+        smart_house.add_device("kitchen", SOCKET_KIND, DeviceConfig::new("Kettle")).unwrap();
+        smart_house.add_device("kitchen", LAMP_KIND, DeviceConfig::new("Lamp")).unwrap();
+        smart_house.add_device("kitchen", THERMOMETER_KIND, DeviceConfig::new("Thermo")).unwrap();
+        smart_house.switch_socket("kitchen", "Lamp", true).unwrap();
+
+        let night = Scene::new("Night")
+            .with_state("hall", "Socket", true)
+            .with_state("kitchen", "Kettle", true)
+            .with_state("kitchen", "Lamp", false);
+        smart_house.add_scene(night).unwrap();
+        let no_device = Scene::new("Away").with_state("hall", "Lamp", false);
+        assert!(matches!(smart_house.add_scene(no_device), Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))));
+        let thermo = Scene::new("Away").with_state("kitchen", "Thermo", false);
+        assert!(matches!(smart_house.add_scene(thermo), Err(SmartHouseError::CommandError(_))));
+        assert!(matches!(smart_house.apply_scene("Away"), Err(SmartHouseError::WrongRequestDataError(SCENE_ERROR))));
+
+        let (sender, receiver) = mpsc::channel();
+        smart_house.subscribe(move |event| sender.send(event.change.clone()).is_ok());
+        smart_house.apply_scene("Night").unwrap();
+        assert!(smart_house.is_switched_on("hall", "Socket").unwrap());
+        assert!(smart_house.is_switched_on("kitchen", "Kettle").unwrap());
+        assert!(!smart_house.is_switched_on("kitchen", "Lamp").unwrap());
+        let changes = receiver.try_iter().collect::<Vec<HouseChange>>();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[3], HouseChange::SceneApplied { scene: String::from("Night") });
+
+        // устройство сцены удалили: сцена не применяется и ничего не меняет
+        smart_house.switch_socket("hall", "Socket", false).unwrap();
+        smart_house.remove_device("kitchen", "Kettle").unwrap();
+        assert!(matches!(smart_house.apply_scene("Night"), Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))));
+        assert!(!smart_house.is_switched_on("hall", "Socket").unwrap());
+
+        // переключение сорвалось посреди сцены: уже переключённые устройства возвращаются обратно
+        smart_house.register_device_kind("wall_switch", |config| {
+            Box::new(WallSwitch { name: config.name.clone(), is_on: config.is_on })
+        });
+        smart_house.add_device("kitchen", "wall_switch", DeviceConfig::new("Switch")).unwrap();
+        let evening = Scene::new("Evening")
+            .with_state("hall", "Socket", true)
+            .with_state("kitchen", "Lamp", true)
+            .with_state("kitchen", "Switch", true);
+        smart_house.add_scene(evening).unwrap();
+        receiver.try_iter().count();
+        assert!(matches!(smart_house.apply_scene("Evening"), Err(SmartHouseError::CommandError(_))));
+        assert!(!smart_house.is_switched_on("hall", "Socket").unwrap());
+        assert!(!smart_house.is_switched_on("kitchen", "Lamp").unwrap());
+        assert!(!receiver.try_iter().any(|c| matches!(c, HouseChange::SceneApplied { .. })));
+
+        // сцены сохраняются вместе с домом, даже если их устройств уже нет
+        smart_house.remove_device("kitchen", "Switch").unwrap();
+        let restored = SmartHouse::from_state(&smart_house.to_state(), Default::default()).unwrap();
+        assert_eq!(restored.get_scenes(), smart_house.get_scenes());
+    }
+
+    #[test]
+    fn test_device_groups() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["hall", "kitchen", "bedroom"]);
+        for (room, name, power) in [("hall", "Heater", 1000.0), ("kitchen", "Heater", 1500.0), ("bedroom", "Heater", 800.0)] {
+            let mut config = DeviceConfig::new(name);
+            config.power = Some(power);
+            smart_house.add_device(room, SOCKET_KIND, config).unwrap();
+        }
+        smart_house.add_device("kitchen", THERMOMETER_KIND, DeviceConfig::new("Thermo")).unwrap();
+
+        let heaters = DeviceGroup::new("heaters")
+            .with_member("hall", "Heater")
+            .with_member("kitchen", "Heater")
+            .with_member("bedroom", "Heater");
+        smart_house.add_group(heaters).unwrap();
+        let kitchen = DeviceGroup::new("kitchen").with_member("kitchen", "Heater").with_member("kitchen", "Thermo");
+        smart_house.add_group(kitchen).unwrap();
+        let no_device = DeviceGroup::new("lamps").with_member("hall", "Lamp");
+        assert!(matches!(smart_house.add_group(no_device), Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))));
+
+        let switched = smart_house.switch_group("heaters", true).unwrap();
+        assert_eq!(switched.iter().map(|(room, _, _)| room.as_str()).collect::<Vec<_>>(), vec!["bedroom", "hall", "kitchen"]);
+        assert!(switched.iter().all(|(_, _, result)| result.is_ok()));
+        let power = smart_house.get_group_power("heaters").unwrap().into_iter()
+            .map(|(_, _, result)| result.unwrap())
+            .sum::<f32>();
+        assert_eq!(power, 3300.0);
+
+        // ошибка одного устройства не мешает остальным
+        let switched = smart_house.switch_group("kitchen", false).unwrap();
+        assert!(switched[0].2.is_ok());
+        assert!(matches!(switched[1].2, Err(SmartHouseError::CommandError(DeviceError::UnsupportedError(_)))));
+        assert!(!smart_house.is_switched_on("kitchen", "Heater").unwrap());
+
+        // удалённые устройства и комнаты пропадают из групп
+        smart_house.remove_device("hall", "Heater").unwrap();
+        smart_house.remove_room("kitchen").unwrap();
+        let groups = smart_house.get_groups();
+        assert_eq!(groups[0].get_members(), vec![("bedroom", "Heater")]);
+        assert!(groups[1].get_members().is_empty());
+        assert_eq!(smart_house.get_group_power("heaters").unwrap().len(), 1);
+
+        let restored = SmartHouse::from_state(&smart_house.to_state(), Default::default()).unwrap();
+        assert_eq!(restored.get_groups(), smart_house.get_groups());
+        smart_house.remove_group("kitchen").unwrap();
+        assert!(smart_house.remove_group("kitchen").is_err());
+        assert!(smart_house.switch_group("kitchen", true).is_err());
+    }
+
+    #[test]
+    fn test_remote_sensors() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["hall", "kitchen"]);
+        smart_house.add_device("hall", THERMOMETER_KIND, DeviceConfig::new("Thermo")).unwrap();
+        smart_house.add_device("kitchen", THERMOMETER_KIND, DeviceConfig::new("Thermo1")).unwrap();
+        smart_house.add_device("kitchen", THERMOMETER_KIND, DeviceConfig::new("Thermo2")).unwrap();
+        smart_house.add_device("kitchen", SOCKET_KIND, DeviceConfig::new("Kettle")).unwrap();
+        let binding = |sensor_id, room: &str, device: &str|
+            SensorBinding { sensor_id, room: String::from(room), device: String::from(device) };
+        smart_house.bind_sensor(binding(1, "kitchen", "Thermo1")).unwrap();
+        smart_house.bind_sensor(binding(2, "kitchen", "Thermo2")).unwrap();
+        assert!(smart_house.bind_sensor(binding(3, "kitchen", "Kettle")).is_err());
+        assert!(smart_house.bind_sensor(binding(3, "hall", "Thermo3")).is_err());
+        assert!(smart_house.get_room_temperature("kitchen").is_err());
+
+        let (sender, receiver) = mpsc::channel();
+        smart_house.subscribe(move |event| sender.send(event.change.clone()).is_ok());
+        let first = SensorReading::new(1, 10, 20.0);
+        assert!(smart_house.set_sensor_reading(first.clone()));
+        assert!(smart_house.set_sensor_reading(SensorReading::new(2, 0, 24.0)));
+        assert_eq!(smart_house.get_temperature("kitchen", "Thermo1").unwrap(), 20.0);
+        assert_eq!(smart_house.get_room_temperature("kitchen").unwrap(), 22.0);
+        assert!(smart_house.get_temperature("hall", "Thermo").is_err());
+        assert_eq!(receiver.try_recv().unwrap(), HouseChange::TemperatureChanged {
+            room: String::from("kitchen"), device: String::from("Thermo1"), before: None, after: 20.0 });
+
+        // повтор и опоздавшая датаграмма не меняют показания
+        assert!(!smart_house.set_sensor_reading(first.clone()));
+        let mut late = first.clone();
+        late.seq = 9;
+        late.temperature = 30.0;
+        assert!(!smart_house.set_sensor_reading(late));
+        assert_eq!(smart_house.get_temperature("kitchen", "Thermo1").unwrap(), 20.0);
+
+        // показание не привязанного датчика получает весь дом, кроме привязанных термометров
+        assert!(smart_house.set_sensor_reading(SensorReading::new(5, 0, 18.0)));
+        assert_eq!(smart_house.get_thermo_data(), 18.0);
+        assert_eq!(smart_house.get_room_temperature("hall").unwrap(), 18.0);
+        assert_eq!(smart_house.get_room_temperature("kitchen").unwrap(), 22.0);
+
+        let sensors = smart_house.get_sensors();
+        assert_eq!(sensors.iter().map(|s| (s.sensor_id, s.health)).collect::<Vec<_>>(),
+                   vec![(1, SensorHealth::Online), (2, SensorHealth::Online), (5, SensorHealth::Online)]);
+        assert_eq!(sensors[0].last_reading, Some(first));
+        assert!(sensors[2].binding.is_none());
+
+        // датчики замолчали: сначала показания устаревают, но ещё выдаются с пометкой в отчёте
+        smart_house.set_sensor_timeouts(SensorTimeouts { stale_after: Duration::ZERO, offline_after: Duration::from_secs(3600) });
+        let status = smart_house.get_temperature_status("kitchen", "Thermo1").unwrap();
+        assert_eq!((status.temperature, status.health), (20.0, SensorHealth::Stale));
+        assert!(status.last_seen.is_some());
+        assert_eq!(smart_house.get_thermo_status().unwrap().health, SensorHealth::Stale);
+        let report = smart_house.create_report();
+        assert_eq!(report.remote_health, Some(SensorHealth::Stale));
+        let kitchen = &report.rooms[1];
+        assert_eq!(kitchen.devices[0].health, None);
+        assert_eq!(kitchen.devices[1].health, Some(SensorHealth::Stale));
+        assert!(kitchen.devices[1].last_seen.is_some());
+        assert!(kitchen.to_string().contains("temperature 20.0, stale, last seen 0s ago"));
+
+        // потом считаются отключёнными, и их старые показания больше не выдаются за текущие
+        smart_house.set_sensor_timeouts(SensorTimeouts { stale_after: Duration::ZERO, offline_after: Duration::ZERO });
+        assert!(matches!(smart_house.get_temperature("kitchen", "Thermo1"),
+                         Err(SmartHouseError::CommandError(DeviceError::ThermoError(SENSOR_OFFLINE_ERROR)))));
+        assert!(smart_house.get_room_temperature("kitchen").is_err());
+        assert!(matches!(smart_house.get_thermo_status(),
+                         Err(SmartHouseError::CommandError(DeviceError::ThermoError(SENSOR_OFFLINE_ERROR)))));
+        assert!(smart_house.get_sensors().iter().all(|s| s.health == SensorHealth::Offline));
+        smart_house.set_sensor_timeouts(SensorTimeouts::default());
+
+        // термометр привязанного датчика удалили: датчик на связи, но его показание получает не весь дом
+        smart_house.remove_device("kitchen", "Thermo2").unwrap();
+        assert!(smart_house.set_sensor_reading(SensorReading::new(2, 1, 25.0)));
+        let sensor = smart_house.get_sensors().into_iter().find(|s| s.sensor_id == 2).unwrap();
+        assert_eq!(sensor.health, SensorHealth::Online);
+        assert_eq!(sensor.last_reading.map(|r| r.temperature), Some(25.0));
+        assert_eq!(smart_house.get_temperature("hall", "Thermo").unwrap(), 18.0);
+        // термометр с тем же именем снова получает показания датчика
+        smart_house.add_device("kitchen", THERMOMETER_KIND, DeviceConfig::new("Thermo2")).unwrap();
+        assert!(smart_house.set_sensor_reading(SensorReading::new(2, 2, 26.0)));
+        assert_eq!(smart_house.get_temperature("kitchen", "Thermo2").unwrap(), 26.0);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut smart_house = SmartHouse::new("SmartHouse", vec!["hall"]);
+        let mut config = DeviceConfig::new("Heater");
+        config.power = Some(1000.0);
+        smart_house.add_device("hall", SOCKET_KIND, config).unwrap();
+        // все значения записываются сейчас и попадают в одну суточную корзину
+        let day = Duration::from_secs(24 * 60 * 60);
+        let history = |smart_house: &SmartHouse, series: SeriesKey|
+            smart_house.get_history(&HistoryQuery::last(series, day, day));
+
+        let readings = [(0, 20.0), (1, 22.0), (2, 24.0)].map(|(seq, t)| SensorReading::new(1, seq, t));
+        for reading in &readings {
+            smart_house.set_sensor_reading(reading.clone());
+        }
+        // повторное показание отброшено и в историю не попало
+        assert!(!smart_house.set_sensor_reading(readings[1].clone()));
+        smart_house.set_thermo_data(19.0);
+        let temperatures = history(&smart_house, SeriesKey::Sensor(1)).unwrap();
+        assert_eq!(temperatures.len(), 1);
+        assert_eq!((temperatures[0].min, temperatures[0].max, temperatures[0].avg, temperatures[0].count),
+                   (20.0, 24.0, 22.0, 3));
+        assert_eq!(history(&smart_house, SeriesKey::Sensor(LEGACY_SENSOR_ID)).unwrap()[0].avg, 19.0);
+        assert!(history(&smart_house, SeriesKey::Sensor(7)).unwrap().is_empty());
+
+        let heater = SeriesKey::Power { room: String::from("hall"), device: String::from("Heater") };
+        smart_house.record_power();
+        smart_house.switch_socket("hall", "Heater", true).unwrap();
+        smart_house.record_power();
+        let power = history(&smart_house, heater.clone()).unwrap();
+        assert_eq!((power[0].min, power[0].max, power[0].count), (0.0, 1000.0, 2));
+
+        smart_house.remove_device("hall", "Heater").unwrap();
+        assert!(matches!(history(&smart_house, heater), Err(SmartHouseError::WrongRequestDataError(DEVICE_ERROR))));
+    }
+}
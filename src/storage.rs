@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::errors::SmartHouseError;
+use crate::errors::SmartHouseError::{SerializationError, StorageError};
+use crate::group::DeviceGroup;
+use crate::scene::Scene;
+
+/*
+    Снимок дома для сохранения на диск. Формат - JSON, чтобы файл можно было
+    поправить руками: комнаты, устройства с их видом, именем и состоянием, сцены и группы.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HouseState {
+    pub name : String,
+    #[serde(default)]
+    pub rooms : Vec<RoomState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scenes : Vec<Scene>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups : Vec<DeviceGroup>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomState {
+    pub name : String,
+    #[serde(default)]
+    pub devices : Vec<DeviceState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceState {
+    pub name : String,
+    pub kind : String,
+    #[serde(default)]
+    pub is_on : bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power : Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness : Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy : Option<f64>,
+}
+
+impl HouseState {
+    pub fn read(path: &Path) -> Result<Self, SmartHouseError> {
+        let data = fs::read_to_string(path).map_err(StorageError)?;
+        serde_json::from_str(&data).map_err(SerializationError)
+    }
+
+    // пишем сначала во временный файл, чтобы не оставить обрезанный файл при ошибке
+    pub fn write(&self, path: &Path) -> Result<(), SmartHouseError> {
+        let data = serde_json::to_string_pretty(self).map_err(SerializationError)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data).map_err(StorageError)?;
+        fs::rename(&tmp_path, path).map_err(StorageError)
+    }
+}